@@ -3,6 +3,13 @@ use super::constant::*;
 
 pub struct BuddyAllocator;
 
+/** No free block is large enough. */
+#[derive(Debug, Clone, Copy)]
+pub struct OutOfMemory;
+
+/** Count of free pages, in all the ranks. */
+static mut FREE_PAGES : usize = 0;
+
 #[inline(always)]
 const fn get_rank(mut size : usize) -> usize {
     let mut rank = 0;
@@ -19,15 +26,15 @@ unsafe fn remove_buddy(num : usize, rank : usize) {
     unlink(set_index(num ^ 1, rank) as _);
 }
 
-/* Find the first non-empty list. */
+/* Find the first non-empty list, if any. */
 #[inline(always)]
-unsafe fn find_first(rank : usize) -> usize{
+unsafe fn find_first(rank : usize) -> Option<usize> {
     let mut ret = rank;
     loop {
-        assert!(rank < TOP_RANK, "Out of memory!");
+        if ret >= TOP_RANK { return None; }
         if !(*rklist(ret)).empty() { break; }
         ret += 1;
-    } return ret;
+    } return Some(ret);
 }
 
 /* Return the given number of start address and rank.  */
@@ -74,9 +81,10 @@ unsafe fn test_and_set(num : usize, rank : usize) -> bool {
 }
 
 /* Try to allocate memory for buddy allocator. */
-unsafe fn try_alloc(rank : usize) -> *mut u8 {
-    let mut top = find_first(rank);
+unsafe fn try_alloc(rank : usize) -> Result<*mut u8, OutOfMemory> {
+    let mut top = find_first(rank).ok_or(OutOfMemory)?;
     let ptr = (*rklist(top)).pop() as *mut u8;
+    FREE_PAGES -= 1 << rank;
 
     let mut num = get_index(ptr, top);
     set_busy(num);
@@ -90,11 +98,12 @@ unsafe fn try_alloc(rank : usize) -> *mut u8 {
         set_free(num | 1);  // Right child as free.
     }
 
-    return ptr;
+    return Ok(ptr);
 }
 
 /* Try to deallocate a memory piece. */
 unsafe fn try_dealloc(mut num : usize, mut rank : usize) {
+    FREE_PAGES += 1 << rank;
     // Go to parent...
     while test_and_set(num, rank) { num >>= 1; rank += 1; }
 
@@ -106,6 +115,7 @@ unsafe fn build(index : usize, l : usize, r : usize, beg : usize , end : usize,
     /* Exactly the node. */
     if l == beg && r == end {
         set_free(index);
+        FREE_PAGES += 1 << rank;
         let index = set_index(index, rank);
         return rklist(rank).push(index as _);
     } else {
@@ -138,9 +148,9 @@ impl BuddyAllocator {
         BuddyAllocator::debug();
     }
 
-    /** Allocate an arbitary size of memory (aligned to page). */
+    /** Allocate an arbitary size of memory (aligned to page). Null if out of memory. */
     pub unsafe fn allocate(size : usize) -> *mut u8 {
-        return try_alloc(get_rank(size));
+        return try_alloc(get_rank(size)).unwrap_or(core::ptr::null_mut());
     }
     /** Deallocate an arbitary size of memory (aligned to page). */
     pub unsafe fn deallocate(ptr : *mut u8, size : usize) {
@@ -149,7 +159,7 @@ impl BuddyAllocator {
     }
 
     /** Allocate exactly one page. */
-    pub unsafe fn allocate_page() -> *mut u8 {
+    pub unsafe fn allocate_page() -> Result<*mut u8, OutOfMemory> {
         return try_alloc(BuddyAllocator::PAGE_RANK);
     }
     /** Deallocate exactly one page.  */
@@ -158,6 +168,11 @@ impl BuddyAllocator {
         return try_dealloc(get_index(ptr, BuddyAllocator::PAGE_RANK), BuddyAllocator::PAGE_RANK);
     }
 
+    /** Return the count of free pages. */
+    pub unsafe fn free_pages() -> usize {
+        return FREE_PAGES;
+    }
+
     /** An inner debug interface. */
     pub unsafe fn debug() {
         warning!("Base address: {:p}", BUDDY_START);
@@ -5,12 +5,16 @@ mod constant;
 mod page_impl;
 mod page_mmap;
 mod page_copy;
+mod page_share;
+mod slab;
 
 pub use constant::KERNEL_SATP;
 pub use constant::PAGE_SIZE;
 pub use page::PageAddress;
 pub use page::PTEFlag;
 pub use page_copy::CheckError;
+pub use page_share::SharedPages;
+pub use buddy::OutOfMemory;
 
 use constant::*;
 use core::alloc::{GlobalAlloc, Layout};
@@ -26,13 +30,16 @@ global_asm!(include_str!("alloc.asm"));
 
 struct Dummy;
 
+/** Small objects come from the slabs, and the others from the buddy allocator. */
 unsafe impl GlobalAlloc for Dummy {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let size = max(layout.size(), layout.align());
+        if size <= slab::SLAB_LIMIT { return slab::allocate(size); }
         return BuddyAllocator::allocate(size);
     }
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         let size = max(layout.size(), layout.align());
+        if size <= slab::SLAB_LIMIT { return slab::deallocate(ptr, size); }
         return BuddyAllocator::deallocate(ptr, size);
     }
 }
@@ -40,7 +47,7 @@ unsafe impl GlobalAlloc for Dummy {
 #[global_allocator]
 static GLOBAL_ALLOCATOR : Dummy = Dummy;
 
-pub fn allocate_one_page() -> *mut u8 {
+pub fn allocate_one_page() -> Result<*mut u8, OutOfMemory> {
     unsafe { BuddyAllocator::allocate_page() }
 }
 
@@ -48,6 +55,11 @@ pub fn deallocate_one_page(ptr : *mut u8) {
     unsafe { BuddyAllocator::deallocate_page(ptr) }
 }
 
+/** Return the count of free pages. */
+pub fn count_free_pages() -> usize {
+    unsafe { BuddyAllocator::free_pages() }
+}
+
 /**
  * Call this function to initialize the buddy system.
  * It will automatically set up the page table.
@@ -55,6 +67,7 @@ pub fn deallocate_one_page(ptr : *mut u8) {
 pub unsafe fn init(mem_end : usize)  {
     extern "C" { fn ekernel(); }
     BuddyAllocator::first_init(ekernel as _, mem_end);
+    slab::init();
     // logging!("Buddy allocator initialized! {} MiB in all!", (PAGE_SIZE << rank) >> 20);
     page::init_page_table();
 }
@@ -89,4 +102,8 @@ pub unsafe fn demo() {
 }
 
 /** Display the memory usage of the allocator. */
-pub unsafe fn display() { BuddyAllocator::debug(); }
+pub unsafe fn display() {
+    BuddyAllocator::debug();
+    slab::debug();
+    message!("Free pages: {}", BuddyAllocator::free_pages());
+}
@@ -3,7 +3,7 @@ use bitflags::bitflags;
 use crate::alloc::print_separator;
 use crate::alloc::get_mem_end;
 use crate::get_zero_page;
-use super::{buddy::BuddyAllocator, constant::*};
+use super::{buddy::{BuddyAllocator, OutOfMemory}, constant::*};
 
 #[derive(Clone, Copy)]
 pub struct PageAddress(usize);
@@ -16,7 +16,7 @@ pub(super) enum PTEOwner {
     Kernel   = 0,   // Kernel owned, no need to destruct.
     Process  = 1,   // Process owned, destructed when process exits.
     Shared   = 2,   // Shared by multiple processes, need reference counting.
-    Reserved = 3,   // Reserved for future use.
+    CopyOnWrite = 3, // Shared until written, need reference counting.
 }
 
 /**
@@ -26,7 +26,7 @@ pub(super) enum PTEOwner {
  * - 00: Default, a PTE that doesn't own any permission.
  * - 01: A PTE owned by current process, destructed when process exits.
  * - 10: A PTE shared by multiple processes, need reference counting.
- * - 11: Not used yet.
+ * - 11: A PTE shared after fork, copied on the first store (W cleared).
  */
 pub(super) const V : PTEFlag = PTEFlag(1 << 0);
 pub(super) const R : PTEFlag = PTEFlag(1 << 1);
@@ -76,7 +76,7 @@ pub unsafe fn init_page_table() {
 
     // Set kernel part using middle/normal size page.
     // Set the second level page table.
-    let mut page = PageAddress::new_pagetable();
+    let mut page = PageAddress::new_pagetable().expect("Out of memory!");
     root[2].set_entry(page, PTEFlag::NEXT);
 
     // Set the kernel memory as read/write only.
@@ -96,7 +96,7 @@ pub unsafe fn init_page_table() {
     let mids = (size + MIDDLE_PAGE_SIZE - 1) / MIDDLE_PAGE_SIZE;
     message!("Middle pages {}", mids);
     for i in 0..mids {
-        page[i].set_entry(PageAddress::new_pagetable(), PTEFlag::NEXT);
+        page[i].set_entry(PageAddress::new_pagetable().expect("Out of memory!"), PTEFlag::NEXT);
     }
 
     init_kernel_page(page, mids);
@@ -208,12 +208,23 @@ impl PTEFlag {
             0b00 => PTEOwner::Kernel,
             0b01 => PTEOwner::Process,
             0b10 => PTEOwner::Shared,
-            0b11 => PTEOwner::Reserved,
+            0b11 => PTEOwner::CopyOnWrite,
             _    => unreachable!(),
         }
     }
 }
 
+impl PTEFlag {
+    /** The same permission, owned by %owner instead. */
+    pub(super) const fn with_owner(self, owner : PTEOwner) -> Self {
+        PTEFlag((self.0 & !RSV.0) | owner.to_flag().0)
+    }
+    /** The same flags, without those in %flag. */
+    pub(super) const fn without(self, flag : PTEFlag) -> Self {
+        PTEFlag(self.0 & !flag.0)
+    }
+}
+
 impl PTEOwner {
     #[inline(always)]
     pub(super) const fn to_flag(&self) -> PTEFlag {
@@ -221,16 +232,16 @@ impl PTEOwner {
             PTEOwner::Kernel   => PTEFlag(0b00 << 8),
             PTEOwner::Process  => PTEFlag(0b01 << 8),
             PTEOwner::Shared   => PTEFlag(0b10 << 8),
-            PTEOwner::Reserved => panic!("Reserved is not allowed."),
+            PTEOwner::CopyOnWrite => PTEFlag(0b11 << 8),
         }
     }
 }
 
 impl PageAddress {
     /** Return a zero-filled page for page table. */
-    pub fn new_pagetable() -> Self { unsafe { allocate_zero() } }
+    pub fn new_pagetable() -> Result<Self, OutOfMemory> { unsafe { allocate_zero() } }
     /** Return an uninitialized page with random bits. */
-    pub fn new_rand_page() -> Self { unsafe { allocate_page() } }
+    pub fn new_rand_page() -> Result<Self, OutOfMemory> { unsafe { allocate_page() } }
     /** Return a page with given physical address entry. */
     pub const fn new_usize(num : usize) -> Self { PageAddress(num >> 12) }
     /** Free only this page. */
@@ -290,8 +301,8 @@ unsafe fn set_special_identity(page : PageAddress, i : usize, j : usize, k : usi
     page[k & 0x1FF].set_entry(PageAddress::new_normal(i as _, j as _, k as _), flag);
 }
 
-unsafe fn allocate_zero() -> PageAddress {
-    let addr = BuddyAllocator::allocate_page();
+unsafe fn allocate_zero() -> Result<PageAddress, OutOfMemory> {
+    let addr = BuddyAllocator::allocate_page()?;
 
     // warning!("Zero-filled page allocated at {:p}", addr);
 
@@ -299,13 +310,13 @@ unsafe fn allocate_zero() -> PageAddress {
     let temp = addr as *mut usize;
     for i in 0..512 { *temp.wrapping_add(i) = 0; }
 
-    return PageAddress::new_ptr(addr);
+    return Ok(PageAddress::new_ptr(addr));
 }
 
-unsafe fn allocate_page() -> PageAddress {
-    let addr = BuddyAllocator::allocate_page();
+unsafe fn allocate_page() -> Result<PageAddress, OutOfMemory> {
+    let addr = BuddyAllocator::allocate_page()?;
     // warning!("Uninitialized page allocated at {:p}", addr);
-    return PageAddress::new_ptr(addr);
+    return Ok(PageAddress::new_ptr(addr));
 }
 
 extern "C" { fn end_entry(); }
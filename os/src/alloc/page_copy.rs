@@ -1,8 +1,10 @@
+use core::cmp::min;
+
 use crate::alloc::page::{R, U, V, W};
 use crate::alloc::{page::PTEOwner, PTEFlag, PAGE_SIZE};
 use crate::utility::*;
 
-use super::{page::PageTableEntry, PageAddress};
+use super::{page::PageTableEntry, OutOfMemory, PageAddress};
 
 struct PageIterator {
     huge    : *mut PageTableEntry,
@@ -21,9 +23,10 @@ impl PageAddress {
      * Copy from a kernel pointer to a user pointer.
      * This will check the permission of the user pointer.
      * It should be at least U + W + V.
+     * Return false if some page is not, and then the copy stops.
      */
-    pub unsafe fn core_to_user <T : CanCopy> (self, dst : usize, len : usize, src : T) {
-        if len == 0 { return; }
+    pub unsafe fn core_to_user <T : CanCopy> (self, dst : usize, len : usize, src : T) -> bool {
+        if len == 0 { return true; }
         return copy_to_user_impl(self, dst, len, src);
     }
 
@@ -37,6 +40,17 @@ impl PageAddress {
         return user_to_core_impl(self, dst, src, len);
     }
 
+    /**
+     * Copy from a user pointer in %root to a user pointer in self,
+     * without any intermediate kernel buffer.
+     * Source should be at least U + R + V, target at least U + W + V.
+     * Return false if some page is missing, and then the copy stops.
+     */
+    pub unsafe fn user_to_user(self, dst : usize, root : PageAddress, src : usize, len : usize) -> bool {
+        if len == 0 { return true; }
+        return user_to_user_impl(self, dst, root, src, len);
+    }
+
     /** Validate the input pointer. */
     pub unsafe fn check_ptr(self, dst : usize, len : usize, flag : PTEFlag) -> CheckError {
         if len == 0 { return CheckError::Nothing; }
@@ -46,7 +60,8 @@ impl PageAddress {
         return check_pointer(self, dst, page_end - page_beg, flag);
     }
 
-    pub unsafe fn copy_from(self, root : PageAddress) {
+    /** Share the user pages of %root. If out of memory, some may be left out. */
+    pub unsafe fn copy_from(self, root : PageAddress) -> Result<(), OutOfMemory> {
         return copy_impl(self, root);
     }
 
@@ -72,6 +87,16 @@ impl PageAddress {
     pub unsafe fn free(self) {
         return free_impl(self);
     }
+
+    /** Drop the user pages, but keep the page tables and kernel pages. */
+    pub unsafe fn release(self) {
+        return release_impl(self);
+    }
+
+    /** Count the user pages owned only by this page table. */
+    pub unsafe fn count_private(self) -> usize {
+        return count_impl(self);
+    }
 }
 
 impl PageIterator {
@@ -124,30 +149,35 @@ unsafe fn inc_test(addr : *mut *mut PageTableEntry) -> bool {
 }
 
 unsafe fn copy_to_user_impl <T : CanCopy> (
-    root : PageAddress, dst : usize, mut len : usize, src : T) {
+    root : PageAddress, dst : usize, mut len : usize, src : T) -> bool {
     let offset      = block_offset(dst);
-    let mut iter    = root.get_iterator(dst).unwrap();
+    let mut iter    = match root.get_iterator(dst) {
+        Some(iter)  => iter,
+        None        => return false,
+    };
     let (addr,flag) = iter.get_address_flag();
     let addr        = addr.wrapping_add(offset);
     let mut src     = src;
 
-    assert!(flag.contains(U | W | V), "Invalid page table mapping!");
+    if !flag.contains(U | W | V) { return false; }
 
     let remain = PAGE_SIZE - offset; // Remaining bytes in the first page.
     if len <= remain {
-        return src.copy_n(core::slice::from_raw_parts_mut(addr, len));
-    } else {
-        len -= remain;
-        src.copy_n(core::slice::from_raw_parts_mut(addr, remain));
-        while len >= PAGE_SIZE {
-            iter.inc();
-            len -= PAGE_SIZE;
-            let (addr, flag) = iter.get_address_flag();
-            assert!(flag.contains(U | W | V), "Invalid page table mapping!");
-            src.copy_n(core::slice::from_raw_parts_mut(addr, PAGE_SIZE));
-        }
         src.copy_n(core::slice::from_raw_parts_mut(addr, len));
+        return true;
+    }
+
+    len -= remain;
+    src.copy_n(core::slice::from_raw_parts_mut(addr, remain));
+    while len > 0 {
+        iter.inc();
+        let (addr, flag) = iter.get_address_flag();
+        if !flag.contains(U | W | V) { return false; }
+        let size = min(len, PAGE_SIZE);
+        src.copy_n(core::slice::from_raw_parts_mut(addr, size));
+        len -= size;
     }
+    return true;
 }
 
 unsafe fn user_to_core_impl <T : CanPush> (
@@ -176,6 +206,35 @@ unsafe fn user_to_core_impl <T : CanPush> (
     }
 }
 
+unsafe fn user_to_user_impl(
+    dst_root : PageAddress, mut dst : usize, src_root : PageAddress, mut src : usize, mut len : usize) -> bool {
+    while len > 0 {
+        let (dst_addr, dst_flag) = match dst_root.get_iterator(dst) {
+            Some(iter) => iter.get_address_flag(),
+            None => return false,
+        };
+        let (src_addr, src_flag) = match src_root.get_iterator(src) {
+            Some(iter) => iter.get_address_flag(),
+            None => return false,
+        };
+        if !dst_flag.contains(U | W | V) || !src_flag.contains(U | R | V) { return false; }
+
+        // Copy until either side reaches the end of its page.
+        let dst_remain  = PAGE_SIZE - block_offset(dst);
+        let src_remain  = PAGE_SIZE - block_offset(src);
+        let size        = min(len, min(dst_remain, src_remain));
+
+        let dst_addr    = dst_addr.wrapping_add(block_offset(dst));
+        let src_addr    = src_addr.wrapping_add(block_offset(src));
+        dst_addr.copy_from_nonoverlapping(src_addr, size);
+
+        dst += size;
+        src += size;
+        len -= size;
+    }
+    return true;
+}
+
 /**
  * Validate the pointer in a range of pages.
  */
@@ -200,32 +259,47 @@ unsafe fn check_pointer(
     }
 }
 
-unsafe fn copy_impl(dst : PageAddress, src : PageAddress) {
+/**
+ * Share the user pages of %src with %dst, instead of copying them.
+ * Writable pages become read-only in both, and are copied on write.
+ * Read-only pages (e.g. code) are just shared.
+ */
+unsafe fn copy_impl(dst : PageAddress, src : PageAddress) -> Result<(), OutOfMemory> {
     for i in 0..512 {
         let (addr, flag) = src[i].get_entry();
         if flag == PTEFlag::INVALID { continue; }
         assert!(flag == PTEFlag::NEXT, "Invalid page table mapping!");
         for j in 0..512 {
-            let (addr, flag) = addr[j].get_entry();
+            let (mut addr, flag) = addr[j].get_entry();
             if flag == PTEFlag::INVALID { continue; }
             assert!(flag == PTEFlag::NEXT, "Invalid page table mapping!");
             for k in 0..512 {
-                let (addr, flag) = addr[k].get_entry();
+                let current = &mut addr[k];
+                let (addr, flag) = current.get_entry();
                 if flag == PTEFlag::INVALID { continue; }
+                let virt = (i << 30) | (j << 21) | (k << 12);
                 match flag.get_owner() {
                     PTEOwner::Kernel => {
                         /* Kernel should have done it previously  */
                     },
                     PTEOwner::Process => {
-                        use core::slice::from_raw_parts;
-                        let page = dst.try_umap((i << 30) | (j << 21) | (k << 12), flag);
-                        page.copy_at(0, from_raw_parts(addr.address(), PAGE_SIZE));
+                        addr.make_shared();
+                        let flag = if flag.contains(W) {
+                            flag.without(W).with_owner(PTEOwner::CopyOnWrite)
+                        } else {
+                            flag.with_owner(PTEOwner::Shared)
+                        };
+                        current.set_flag(flag);
+                        dst.refer_umap(virt, addr, flag)?;
+                    },
+                    PTEOwner::Shared | PTEOwner::CopyOnWrite => {
+                        dst.refer_umap(virt, addr, flag)?;
                     },
-                    _ => todo!("Implement support for other owners."),
                 }
             }
         }
     }
+    return Ok(());
 }
 
 unsafe fn free_impl(mut root : PageAddress) {
@@ -251,7 +325,10 @@ unsafe fn free_impl(mut root : PageAddress) {
                         addr.free_this(); // Free only one page.
                         current.reset();
                     },
-                    _ => todo!("Implement support for other owners."),
+                    PTEOwner::Shared | PTEOwner::CopyOnWrite => {
+                        addr.unshare(); // Freed by the last one.
+                        current.reset();
+                    },
                 }
             }
             addr.free_this();
@@ -262,3 +339,43 @@ unsafe fn free_impl(mut root : PageAddress) {
     }
     root.free_this();
 }
+
+unsafe fn release_impl(root : PageAddress) {
+    for i in 0..512 {
+        let (addr, flag) = root[i].get_entry();
+        if flag == PTEFlag::INVALID { continue; }
+        for j in 0..512 {
+            let (mut addr, flag) = addr[j].get_entry();
+            if flag == PTEFlag::INVALID { continue; }
+            for k in 0..512 {
+                let current = &mut addr[k];
+                let (addr, flag) = current.get_entry();
+                if flag == PTEFlag::INVALID { continue; }
+                match flag.get_owner() {
+                    PTEOwner::Kernel => {},
+                    PTEOwner::Process => addr.free_this(),
+                    PTEOwner::Shared | PTEOwner::CopyOnWrite => addr.unshare(),
+                }
+                if !matches!(flag.get_owner(), PTEOwner::Kernel) { current.reset(); }
+            }
+        }
+    }
+}
+
+unsafe fn count_impl(root : PageAddress) -> usize {
+    let mut count = 0;
+    for i in 0..512 {
+        let (addr, flag) = root[i].get_entry();
+        if flag == PTEFlag::INVALID { continue; }
+        for j in 0..512 {
+            let (addr, flag) = addr[j].get_entry();
+            if flag == PTEFlag::INVALID { continue; }
+            for k in 0..512 {
+                let (_, flag) = addr[k].get_entry();
+                if flag == PTEFlag::INVALID { continue; }
+                if matches!(flag.get_owner(), PTEOwner::Process) { count += 1; }
+            }
+        }
+    }
+    return count;
+}
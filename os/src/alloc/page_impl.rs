@@ -70,7 +70,7 @@ impl PTEFlag {
             PTEOwner::Kernel    => { uart_print!(" | Kernel"); }
             PTEOwner::Process   => { uart_print!(" | Process"); }
             PTEOwner::Shared    => { uart_print!(" | Shared"); }
-            PTEOwner::Reserved  => { uart_print!(" | Reserved"); }
+            PTEOwner::CopyOnWrite => { uart_print!(" | CopyOnWrite"); }
         }
         uart_print!("\n");
     }
@@ -1,34 +1,173 @@
-use super::{page::{PTEOwner, PageTableEntry, U}, PTEFlag, PageAddress};
+use super::{page::{PTEOwner, PageTableEntry, U, W}, OutOfMemory, PTEFlag, PageAddress, PAGE_SIZE};
 
 impl PageAddress {
     /** Add a supervisor mapping. */
-    pub unsafe fn smap(self, virt : usize, phys : PageAddress, flag : PTEFlag) {
+    pub unsafe fn smap(self, virt : usize, phys : PageAddress, flag : PTEFlag) -> Result<(), OutOfMemory> {
         return mmap(self, virt, phys, flag | PTEOwner::Kernel.to_flag());
     }
     /** Try to add a supervisor mapping. If existed, throw. */
-    pub unsafe fn new_smap(self, virt : usize, flag : PTEFlag) -> PageAddress {
+    pub unsafe fn new_smap(self, virt : usize, flag : PTEFlag) -> Result<PageAddress, OutOfMemory> {
         return new_mmap(self, virt, flag | PTEOwner::Kernel.to_flag());
     }
     /** Add a user-defined mapping. */
-    pub unsafe fn umap(self, virt : usize, phys : PageAddress, flag : PTEFlag) {
+    pub unsafe fn umap(self, virt : usize, phys : PageAddress, flag : PTEFlag) -> Result<(), OutOfMemory> {
         return mmap(self, virt, phys, flag | PTEOwner::Process.to_flag() | U);
     }
     /** Try to add a mapping. If existed, throw. */
-    pub unsafe fn new_umap(self, virt : usize, flag : PTEFlag) -> PageAddress {
+    pub unsafe fn new_umap(self, virt : usize, flag : PTEFlag) -> Result<PageAddress, OutOfMemory> {
         return new_mmap(self, virt, flag | PTEOwner::Process.to_flag() | U);
     }
     /** Try to add a mapping. If existed, just add to the flags. */
-    pub unsafe fn try_umap(self, virt : usize, flag : PTEFlag) -> PageAddress {
+    pub unsafe fn try_umap(self, virt : usize, flag : PTEFlag) -> Result<PageAddress, OutOfMemory> {
         return try_mmap(self, virt, flag | PTEOwner::Process.to_flag() | U);
     }
     pub unsafe fn try_unumap(self, virt : usize) -> bool {
         return try_unumap(self, virt);
     }
+    /**
+     * Lend %count pages starting at %src in %root to self at %virt, read-only.
+     * Each page gains a reference, so it outlives %root if needed.
+     * Private pages of %root become shared, and writable ones are copied
+     * on write, as in fork. Return false if some page is not readable.
+     * If failed, nothing is left mapped.
+     */
+    pub unsafe fn lend_umap(self, virt : usize, root : PageAddress, src : usize, count : usize) -> Result<bool, OutOfMemory> {
+        for i in 0..count {
+            let offset  = i * PAGE_SIZE;
+            let leaf    = match find_leaf(root, src + offset) {
+                Some(leaf) => &mut *leaf,
+                None => {
+                    self.lend_unumap(virt, i);
+                    return Ok(false);
+                },
+            };
+            let (phys, flag) = leaf.get_entry();
+            if !flag.contains(PTEFlag::RO | U) || matches!(flag.get_owner(), PTEOwner::Kernel) {
+                self.lend_unumap(virt, i);
+                return Ok(false);
+            }
+            if matches!(flag.get_owner(), PTEOwner::Process) {
+                phys.make_shared();
+                leaf.set_flag(if flag.contains(W) {
+                    flag.without(W).with_owner(PTEOwner::CopyOnWrite)
+                } else {
+                    flag.with_owner(PTEOwner::Shared)
+                });
+            }
+            let result = self.refer_umap(virt + offset, phys, PTEFlag::RO.with_owner(PTEOwner::Shared));
+            if let Err(error) = result {
+                self.lend_unumap(virt, i);
+                return Err(error);
+            }
+        }
+        return Ok(true);
+    }
+    /**
+     * Map the shared %pages at %virt, each gaining a reference.
+     * If out of memory, nothing is left mapped.
+     */
+    pub unsafe fn share_umap(self, virt : usize, pages : &[PageAddress], flag : PTEFlag) -> Result<(), OutOfMemory> {
+        for (i, phys) in pages.iter().enumerate() {
+            let result = self.refer_umap(virt + i * PAGE_SIZE, *phys, flag.with_owner(PTEOwner::Shared));
+            if result.is_err() {
+                self.share_unumap(virt, i);
+                return result;
+            }
+        }
+        return Ok(());
+    }
+    /** Map the reference-counted %phys at %virt as %flag (with the owner). */
+    pub(super) unsafe fn refer_umap(self, virt : usize, phys : PageAddress, flag : PTEFlag) -> Result<(), OutOfMemory> {
+        mmap(self, virt, phys, flag | U)?;
+        phys.share();
+        return Ok(());
+    }
+    /** Whether some user page is mapped at %virt. */
+    pub unsafe fn is_umapped(self, virt : usize) -> bool {
+        match find_leaf(self, virt) {
+            Some(leaf) => (*leaf).get_entry().1 != PTEFlag::INVALID,
+            None => false,
+        }
+    }
+    /**
+     * Change the permission of the page at %virt to %flag, if mapped.
+     * Pages to be copied on write stay read-only until the first store.
+     * So do shared pages made writable, since others still map them.
+     */
+    pub unsafe fn protect(self, virt : usize, flag : PTEFlag) {
+        let leaf = match find_leaf(self, virt) {
+            Some(leaf) => &mut *leaf,
+            None => return,
+        };
+        let (_, old) = leaf.get_entry();
+        if old == PTEFlag::INVALID { return; }
+        let owner = match old.get_owner() {
+            PTEOwner::Shared if flag.contains(W) => PTEOwner::CopyOnWrite,
+            owner => owner,
+        };
+        let flag = match owner {
+            PTEOwner::CopyOnWrite => flag.without(W),
+            _ => flag,
+        };
+        leaf.set_flag(flag.with_owner(owner) | U);
+    }
+    /**
+     * Give a private copy of the page at %virt, on the first store.
+     * Return false if it is not a copy-on-write page.
+     */
+    pub unsafe fn copy_on_write(self, virt : usize) -> Result<bool, OutOfMemory> {
+        let leaf = match find_leaf(self, virt) {
+            Some(leaf) => &mut *leaf,
+            None => return Ok(false),
+        };
+        let (phys, flag) = leaf.get_entry();
+        if flag == PTEFlag::INVALID || !matches!(flag.get_owner(), PTEOwner::CopyOnWrite) {
+            return Ok(false);
+        }
+
+        let flag = flag.with_owner(PTEOwner::Process) | W;
+        if phys.try_claim() {
+            // The others are gone, so just take it back.
+            leaf.set_flag(flag);
+        } else {
+            let page = PageAddress::new_rand_page()?;
+            page.copy_at(0, core::slice::from_raw_parts(phys.address(), PAGE_SIZE));
+            phys.unshare();
+            leaf.set_entry(page, flag);
+        }
+        return Ok(true);
+    }
+    /**
+     * Give private copies of the pages in [%virt, %virt + %len) to be
+     * copied on write, e.g. a buffer that the kernel is to write.
+     */
+    pub unsafe fn break_cow(self, virt : usize, len : usize) -> Result<(), OutOfMemory> {
+        if len == 0 { return Ok(()); }
+        let first = virt / PAGE_SIZE;
+        let last  = (virt + len - 1) / PAGE_SIZE;
+        for page in first..=last {
+            self.copy_on_write(page * PAGE_SIZE)?;
+        }
+        return Ok(());
+    }
+    /** Remove %count shared pages at %virt, each losing a reference. */
+    pub unsafe fn share_unumap(self, virt : usize, count : usize) {
+        for i in 0..count {
+            let leaf = &mut *find_leaf(self, virt + i * PAGE_SIZE).expect("Page not shared!");
+            let (phys, _) = leaf.get_entry();
+            phys.unshare();
+            leaf.reset();
+        }
+    }
+    /** Remove %count lent pages at %virt, each losing a reference. */
+    pub unsafe fn lend_unumap(self, virt : usize, count : usize) {
+        return self.share_unumap(virt, count);
+    }
 }
 
 #[inline(never)]
 unsafe fn mmap_until_leaf(
-    mut root : PageAddress, virt : usize, __flag : PTEFlag) -> *mut PageTableEntry {
+    mut root : PageAddress, virt : usize, __flag : PTEFlag) -> Result<*mut PageTableEntry, OutOfMemory> {
     let virt =  virt >> 12;
     let ppn0 = (virt >> 18) & 0x1FF;
     let ppn1 = (virt >> 9 ) & 0x1FF;
@@ -37,7 +176,7 @@ unsafe fn mmap_until_leaf(
     let page = &mut root[ppn0];
     let (addr, flag) = page.get_entry();
     if flag == PTEFlag::INVALID {
-        let temp = PageAddress::new_pagetable();
+        let temp = PageAddress::new_pagetable()?;
         page.set_entry(temp, PTEFlag::NEXT);
         root = temp;
     } else {
@@ -48,7 +187,7 @@ unsafe fn mmap_until_leaf(
     let page = &mut root[ppn1];
     let (addr, flag) = page.get_entry();
     if flag == PTEFlag::INVALID {
-        let temp = PageAddress::new_pagetable();
+        let temp = PageAddress::new_pagetable()?;
         page.set_entry(temp, PTEFlag::NEXT);
         root = temp;
     } else {
@@ -56,40 +195,44 @@ unsafe fn mmap_until_leaf(
         root = addr;
     }
 
-    return &mut root[ppn2];
+    return Ok(&mut root[ppn2]);
 }
 
-unsafe fn mmap(root : PageAddress, virt : usize, phys : PageAddress, __flag : PTEFlag) {
-    let page = &mut *mmap_until_leaf(root, virt, __flag);
+unsafe fn mmap(root : PageAddress, virt : usize, phys : PageAddress, __flag : PTEFlag) -> Result<(), OutOfMemory> {
+    let page = &mut *mmap_until_leaf(root, virt, __flag)?;
     let (_, flag) = page.get_entry();
     assert!(flag == PTEFlag::INVALID, "Mapping existed!");
     page.set_entry(phys, __flag);
+    return Ok(());
 }
 
-unsafe fn new_mmap(root : PageAddress, virt : usize, __flag : PTEFlag) -> PageAddress {
-    let page = &mut *mmap_until_leaf(root, virt, __flag);
+unsafe fn new_mmap(root : PageAddress, virt : usize, __flag : PTEFlag) -> Result<PageAddress, OutOfMemory> {
+    let page = &mut *mmap_until_leaf(root, virt, __flag)?;
     let (_, flag) = page.get_entry();
     assert!(flag == PTEFlag::INVALID, "Mapping existed!");
-    let new = PageAddress::new_pagetable();
+    let new = PageAddress::new_pagetable()?;
     page.set_entry(new, __flag);
-    return new;
+    return Ok(new);
 }
 
-unsafe fn try_mmap(root : PageAddress, virt : usize, __flag : PTEFlag) -> PageAddress {
-    let page = &mut *mmap_until_leaf(root, virt, __flag);
+unsafe fn try_mmap(root : PageAddress, virt : usize, __flag : PTEFlag) -> Result<PageAddress, OutOfMemory> {
+    let page = &mut *mmap_until_leaf(root, virt, __flag)?;
     let (old, flag) = page.get_entry();
     if flag == PTEFlag::INVALID {
-        let phys = PageAddress::new_pagetable();
+        let phys = PageAddress::new_pagetable()?;
         page.set_entry(phys, __flag);
-        return phys;
+        return Ok(phys);
     } else {
         assert!(flag != PTEFlag::NEXT, "Invalid mapping!");
+        // Others still see a shared page, so never make it writable here.
+        if !matches!(flag.get_owner(), PTEOwner::Process) { return Ok(old); }
         page.add_flag(__flag);
-        return old;
+        return Ok(old);
     }
 }
 
-unsafe fn try_unumap(mut root : PageAddress, virt : usize) -> bool {
+/** Find the leaf entry of a virtual address, without any allocation. */
+unsafe fn find_leaf(mut root : PageAddress, virt : usize) -> Option<*mut PageTableEntry> {
     let virt = virt >> 12;
     let ppn0 = (virt >> 18) & 0x1FF;
     let ppn1 = (virt >> 9 ) & 0x1FF;
@@ -98,24 +241,35 @@ unsafe fn try_unumap(mut root : PageAddress, virt : usize) -> bool {
     let page = &mut root[ppn0];
     let (addr, flag) = page.get_entry();
     if flag != PTEFlag::NEXT {
-        return false;
+        return None;
     } 
 
     root = addr;
     let page = &mut root[ppn1];
     let (addr, flag) = page.get_entry();
     if flag != PTEFlag::NEXT {
-        return false;
+        return None;
     }
 
     root = addr;
-    let page = &mut root[ppn2];
+    return Some(&mut root[ppn2]);
+}
+
+unsafe fn try_unumap(root : PageAddress, virt : usize) -> bool {
+    let page = match find_leaf(root, virt) {
+        Some(page) => &mut *page,
+        None => return false,
+    };
     let (addr, flag) = page.get_entry();
     if flag == PTEFlag::INVALID || flag == PTEFlag::NEXT {
         return false;
     }
 
-    addr.free_this();
+    match flag.get_owner() {
+        PTEOwner::Kernel    => return false, // Not owned by the user
+        PTEOwner::Process   => addr.free_this(),
+        PTEOwner::Shared | PTEOwner::CopyOnWrite => addr.unshare(),
+    }
     page.reset();
 
     return true;
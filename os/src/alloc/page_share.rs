@@ -0,0 +1,75 @@
+extern crate alloc;
+use alloc::{collections::BTreeMap, vec::Vec};
+
+use super::{OutOfMemory, PageAddress};
+
+/**
+ * References to those pages owned by PTEOwner::Shared, indexed by the
+ * physical page. A page is freed when its last reference is dropped.
+ * Pages not in the table are not shared at all.
+ */
+static mut SHARED : BTreeMap<usize, usize> = BTreeMap::new();
+
+/** Pages of a shared memory object, which holds one reference to each. */
+pub struct SharedPages(Vec<PageAddress>);
+
+impl PageAddress {
+    /** Return a zero-filled page to share, with one reference. */
+    pub unsafe fn new_shared() -> Result<Self, OutOfMemory> {
+        let page = PageAddress::new_pagetable()?;
+        SHARED.insert(page.bits(), 1);
+        return Ok(page);
+    }
+
+    /** Start counting references to a private page, as the only one. */
+    pub(super) unsafe fn make_shared(self) {
+        SHARED.entry(self.bits()).or_insert(1);
+    }
+
+    /**
+     * Take the page back as private, if no one else refers to it.
+     * Return false if still shared by others.
+     */
+    pub(super) unsafe fn try_claim(self) -> bool {
+        if SHARED.get(&self.bits()) != Some(&1) { return false; }
+        SHARED.remove(&self.bits());
+        return true;
+    }
+
+    /** Add a reference to the shared page. */
+    pub unsafe fn share(self) {
+        let count = SHARED.get_mut(&self.bits()).expect("Page not shared!");
+        *count += 1;
+    }
+
+    /** Drop a reference to the shared page, freeing it if the last. */
+    pub unsafe fn unshare(self) {
+        let count = SHARED.get_mut(&self.bits()).expect("Page not shared!");
+        *count -= 1;
+        if *count == 0 {
+            SHARED.remove(&self.bits());
+            self.free_this();
+        }
+    }
+}
+
+impl SharedPages {
+    /** Allocate %count zero-filled pages. If out of memory, none is kept. */
+    pub unsafe fn new(count : usize) -> Result<Self, OutOfMemory> {
+        let mut pages = Self(Vec::with_capacity(count));
+        for _ in 0..count {
+            pages.0.push(PageAddress::new_shared()?);
+        }
+        return Ok(pages);
+    }
+
+    pub fn get_pages(&self) -> &[PageAddress] {
+        return &self.0;
+    }
+}
+
+impl Drop for SharedPages {
+    fn drop(&mut self) {
+        for page in self.0.iter() { unsafe { page.unshare(); } }
+    }
+}
@@ -0,0 +1,136 @@
+use core::{cmp::max, mem::size_of, ptr::null_mut};
+
+use crate::console::print_separator;
+use super::{buddy::BuddyAllocator, node::{unlink, List, Node}, PAGE_SIZE};
+
+const MIN_SIZE      : usize = 8;    // Size of the smallest class
+const CLASS_COUNT   : usize = 8;    // Classes of 8, 16, ..., 1024 bytes
+
+/** Allocations larger than this go to the buddy allocator. */
+pub(super) const SLAB_LIMIT : usize = MIN_SIZE << (CLASS_COUNT - 1);
+
+/**
+ * Header of a slab, at the front of its page.
+ * The objects follow, aligned to their size.
+ */
+#[repr(C)]
+struct Slab {
+    node    : Node,         // In the list of its class, if not full
+    free    : *mut usize,   // Free objects, linked by the first word
+    used    : usize,        // Count of objects in use
+}
+
+/** A size class, and its statistics. */
+#[derive(Clone, Copy)]
+struct Class {
+    partial : List,     // Slabs with some free objects
+    slabs   : usize,    // Count of slabs (pages) held
+    used    : usize,    // Count of objects in use
+    total   : usize,    // Count of objects ever allocated
+}
+
+static mut CLASSES : [Class; CLASS_COUNT] = [Class::new(); CLASS_COUNT];
+
+impl Class {
+    const fn new() -> Class {
+        Class { partial : List::new(), slabs : 0, used : 0, total : 0 }
+    }
+}
+
+/** The class index of an object of %size bytes. */
+fn get_class(size : usize) -> usize {
+    let size = max(size, MIN_SIZE).next_power_of_two();
+    return (size.trailing_zeros() - MIN_SIZE.trailing_zeros()) as usize;
+}
+
+fn object_size(class : usize) -> usize { MIN_SIZE << class }
+
+/** Offset of the first object in a slab. */
+fn first_offset(class : usize) -> usize { max(size_of::<Slab>(), object_size(class)) }
+
+fn object_count(class : usize) -> usize {
+    return (PAGE_SIZE - first_offset(class)) / object_size(class);
+}
+
+/** Carve a new page into objects of %class. Null if out of memory. */
+unsafe fn new_slab(class : usize) -> *mut Slab {
+    let page = match BuddyAllocator::allocate_page() {
+        Ok(page) => page,
+        Err(_)   => return null_mut(),
+    };
+    let size = object_size(class);
+    let first = first_offset(class);
+
+    // Link from the last, so that the objects are taken in order.
+    let mut free = null_mut();
+    for i in (0..object_count(class)).rev() {
+        let object = page.add(first + i * size) as *mut usize;
+        *object = free as usize;
+        free = object;
+    }
+
+    let slab = page as *mut Slab;
+    (*slab).free = free;
+    (*slab).used = 0;
+    return slab;
+}
+
+/** Call once on init, before any allocation. */
+pub(super) unsafe fn init() {
+    for class in CLASSES.iter_mut() { class.partial.init(); }
+}
+
+/** Allocate an object of %size bytes, aligned to the size class. Null if out of memory. */
+pub(super) unsafe fn allocate(size : usize) -> *mut u8 {
+    let index = get_class(size);
+    let class = CLASSES.get_unchecked_mut(index);
+    if class.partial.empty() {
+        let slab = new_slab(index);
+        if slab.is_null() { return null_mut(); }
+        class.partial.push(slab as *mut Node);
+        class.slabs += 1;
+    }
+
+    let slab = class.partial.head.next as *mut Slab;
+    let object = (*slab).free;
+    (*slab).free = *object as *mut usize;
+    (*slab).used += 1;
+    if (*slab).free.is_null() { unlink(slab as *mut Node); }
+
+    class.used  += 1;
+    class.total += 1;
+    return object as *mut u8;
+}
+
+/** Deallocate an object of %size bytes. */
+pub(super) unsafe fn deallocate(ptr : *mut u8, size : usize) {
+    let class = CLASSES.get_unchecked_mut(get_class(size));
+    let slab = (ptr as usize & !(PAGE_SIZE - 1)) as *mut Slab;
+    let object = ptr as *mut usize;
+
+    // A full slab is back to the list.
+    if (*slab).free.is_null() { class.partial.push(slab as *mut Node); }
+    *object = (*slab).free as usize;
+    (*slab).free = object;
+    (*slab).used -= 1;
+    class.used -= 1;
+
+    // Keep at least one slab of the class, to avoid thrashing.
+    if (*slab).used == 0 && class.slabs > 1 {
+        unlink(slab as *mut Node);
+        class.slabs -= 1;
+        BuddyAllocator::deallocate_page(slab as *mut u8);
+    }
+}
+
+/** An inner debug interface. */
+pub(super) unsafe fn debug() {
+    warning!("Slab classes:");
+    for (index, class) in CLASSES.iter().enumerate() {
+        message!("  Size {}: {} slabs, {}/{} objects used, {} allocated in all",
+            object_size(index), class.slabs, class.used,
+            class.slabs * object_count(index), class.total);
+    }
+    warning!("End of slab debug!");
+    print_separator();
+}
@@ -50,6 +50,11 @@ unsafe fn set_timer_next() {
     *mtimecmp = *mtime + time_scratch.interval;
 }
 
+/** Current time, in the same unit as Time. */
+pub fn get_time() -> usize {
+    return time::read();
+}
+
 impl Time {
     pub fn second(s : usize) -> Self { Time(s * 10000000) }
     pub fn millisecond(ms : usize) -> Self { Time(ms * 10000) }
@@ -1,128 +1,115 @@
-use xmas_elf::{program::{Flags, ProgramHeader}, ElfFile};
+extern crate alloc;
+use alloc::vec::Vec;
+use sys::{check::{check_segments, segment_prot, Segment}, syscall::{PROT_EXEC, PROT_WRITE}};
+use xmas_elf::{program::{Flags, ProgramHeader, Type}, ElfFile};
 
-use crate::{alloc::{PTEFlag, PageAddress, PAGE_SIZE}, get_zero_page};
+use crate::alloc::PTEFlag;
 
-use super::Process;
+use super::{memory::MemoryArea, Process};
 
-unsafe fn get_header_range(ph : ProgramHeader) -> (usize, usize) {
-    let start_va    = ph.virtual_addr() as usize;
-    let end_va      = (ph.virtual_addr() + ph.mem_size()) as usize;
-    let mem_size    = end_va - start_va;
-    assert!(mem_size >= ph.file_size() as usize, "Invalid file size!");
-    return (start_va, mem_size);
+/** A loadable segment of the program. */
+struct Load {
+    segment : Segment,          // Where it is, and the rights
+    data    : &'static [u8],    // Bytes in the image
 }
 
-unsafe fn get_header_permission(ph_flags : Flags) -> PTEFlag {
-    let mut permission = PTEFlag::EMPTY;
-    if ph_flags.is_read() { permission |= PTEFlag::RO; }
-    if ph_flags.is_write() { permission |= PTEFlag::WO; }
-    if ph_flags.is_execute() { permission |= PTEFlag::RX; }
-    return permission;
+impl Load {
+    /** Page flags of the segment, whose rights are checked. */
+    fn get_flag(&self) -> PTEFlag {
+        let prot = self.segment.prot;
+        match prot {
+            PROT_EXEC                       => return PTEFlag::XO,
+            _ if prot & PROT_WRITE != 0     => return PTEFlag::RW,
+            _ if prot & PROT_EXEC != 0      => return PTEFlag::RX,
+            _                               => return PTEFlag::RO,
+        }
+    }
 }
 
-unsafe fn get_header_data<'a>(ph : ProgramHeader<'a>, elf : &'a ElfFile<'a>) -> &'a [u8] {
-    let ph_offset = ph.offset() as usize;
-    let ph_size = ph.file_size() as usize;
-    return &elf.input[ph_offset..ph_offset + ph_size];
+/** A checked program, ready to be loaded into a process. */
+pub(super) struct Program {
+    image   : &'static [u8],
+    entry   : usize,
+    loads   : Vec<Load>,
 }
 
-impl PageAddress {
-    unsafe fn add_range(self, start_va : usize , data : &[u8], permission : PTEFlag) {
-        let offset      = start_va % PAGE_SIZE;
-        let padding     = PAGE_SIZE - offset;
-        let page        = self.try_umap(start_va, permission);
-        if data.len() <= padding {
-            page.copy_at(offset, data);
-        } else {
-            let (mut prefix, mut data) = data.split_at(padding);
-            page.copy_at(offset, prefix);
-
-            let mut now_va = start_va + padding;
-
-            while data.len() >= PAGE_SIZE {
-                let page = self.try_umap(now_va, permission);
-                (prefix, data) = data.split_at(PAGE_SIZE);
-                page.copy_at(0, prefix);
-                now_va += PAGE_SIZE;
-            }
-
-            if data.len() > 0 {
-                let page = self.try_umap(now_va, permission);
-                page.copy_at(0, data);
-            }
-        }
+unsafe fn get_header_range(ph : ProgramHeader) -> Result<(usize, usize), &'static str> {
+    let start_va    = ph.virtual_addr() as usize;
+    let mem_size    = ph.mem_size() as usize;
+    if mem_size < ph.file_size() as usize { return Err("file size exceeds memory size"); }
+    match start_va.checked_add(mem_size) {
+        Some(end_va) if end_va <= MemoryArea::get_program_limit() => {},
+        _ => return Err("segment out of user space"),
     }
+    return Ok((start_va, mem_size));
+}
 
-    unsafe fn add_range_zero(self, start_va : usize, mem_size : usize, permission : PTEFlag) {
-        let offset      = start_va % PAGE_SIZE;
-        let remain      = PAGE_SIZE - offset;
-        let page        = self.try_umap(start_va, permission);
-        let zero_page   = get_zero_page();
-
-        if mem_size <= remain {
-            page.copy_at(offset, &zero_page[..mem_size]);
-        } else {
-            page.copy_at(offset, &zero_page[..remain]);
-            let mut now_va = start_va + remain;
+/** Rights (PROT_*) of %ph_flags. Writable and executable is not allowed. */
+unsafe fn get_header_permission(ph_flags : Flags) -> Result<usize, &'static str> {
+    return segment_prot(ph_flags.is_read(), ph_flags.is_write(), ph_flags.is_execute());
+}
 
-            let mut rest = mem_size - remain;
-            while rest >= PAGE_SIZE {
-                let page = self.new_umap(now_va, permission);
-                page.copy_at(0, zero_page);
-                now_va += PAGE_SIZE;
-                rest -= PAGE_SIZE;
-            }
+unsafe fn get_header_data<'a>(ph : ProgramHeader, elf : &ElfFile<'a>) -> Result<&'a [u8], &'static str> {
+    let ph_offset = ph.offset() as usize;
+    let ph_size = ph.file_size() as usize;
+    let input : &'a [u8] = elf.input;
+    return match ph_offset.checked_add(ph_size) {
+        Some(end) if end <= input.len() => Ok(&input[ph_offset..end]),
+        _ => Err("segment out of file"),
+    };
+}
 
-            if rest > 0 {
-                let page = self.try_umap(now_va, permission);
-                page.copy_at(0, &zero_page[..rest]);
-            }
+impl Program {
+    /**
+     * Check the program in %data, before anything of the process
+     * is dropped. Return the reason if it is malformed.
+     */
+    pub(super) unsafe fn parse(data : &'static [u8]) -> Result<Program, &'static str> {
+        let elf = ElfFile::new(data).map_err(|_| "invalid elf")?;
+        let elf_header = elf.header;
+        if elf_header.pt1.magic != [0x7f, 0x45, 0x4c, 0x46] { return Err("invalid elf"); }
+        let ph_count = elf_header.pt2.ph_count();
+        let mut loads = Vec::new();
+        for i in 0..ph_count {
+            let ph = elf.program_header(i).map_err(|_| "invalid program header")?;
+            if ph.get_type().map_err(|_| "invalid program header")? != Type::Load { continue; }
+            let (start, size) = get_header_range(ph)?;
+            if size == 0 { continue; }
+            let prot = get_header_permission(ph.flags())?;
+            let data = get_header_data(ph, &elf)?;
+            loads.push(Load { segment : Segment { start, size, prot }, data });
         }
-    }
 
-    /** Load a program header from an ELF file. */
-    pub unsafe fn load_from_elf(&mut self, ph : ProgramHeader, elf : &ElfFile) -> usize {
-        let (start_va, mem_size) = get_header_range(ph);
-        if mem_size == 0 { return start_va; }
+        let segments : Vec<Segment> = loads.iter().map(|x| x.segment).collect();
+        check_segments(&segments)?;
+        let entry = elf_header.pt2.entry_point() as usize;
+        let executable = segments.iter().any(|x| {
+            x.prot & PROT_EXEC != 0 && x.start <= entry && entry < x.start + x.size
+        });
+        if !executable { return Err("entry point not executable"); }
 
-        let permission  = get_header_permission(ph.flags());
-        let data        = get_header_data(ph, elf);
-
-        self.add_range(start_va, data, permission);
-
-        // The rest bits are set to zero.
-        if mem_size != data.len() {
-            let remain = mem_size - data.len();
-            self.add_range_zero(start_va + data.len(), remain, permission);
-        }
-        return start_va + mem_size;
+        return Ok(Program { image : data, entry, loads });
     }
 }
 
 impl Process {
-    pub unsafe fn init_from_elf(&mut self, data : &[u8]) {
-        let elf = xmas_elf::ElfFile::new(&data).unwrap();
-        let elf_header = elf.header;
-        let magic = elf_header.pt1.magic;
-        assert_eq!(magic, [0x7f, 0x45, 0x4c, 0x46], "invalid elf!");
-        let ph_count = elf_header.pt2.ph_count();
+    /**
+     * Load the checked %program. The segments are only recorded,
+     * and their pages are filled on the first fault.
+     */
+    pub(super) unsafe fn init_from_elf(&mut self, program : &Program) {
+        let memory = self.get_memory_area();
         let mut max_vaddr = 0;
-        for i in 0..ph_count {
-            let ph = elf.program_header(i).unwrap();
-            if ph.get_type().unwrap() == xmas_elf::program::Type::Load {
-                // message!("{}", ph);
-                let vaddr_end = self.get_satp().load_from_elf(ph, &elf);
-                if vaddr_end > max_vaddr {
-                    max_vaddr = vaddr_end;
-                }
-            }
+        for load in program.loads.iter() {
+            let segment = &load.segment;
+            memory.add_segment(program.image, segment.start, segment.size, load.data, load.get_flag());
+            max_vaddr = max_vaddr.max(segment.start + segment.size);
         }
 
-        let memory = self.get_memory_area();
-
         message!("Program end: {:#x}", memory.set_program_end(max_vaddr));
 
         let trap_frame = self.get_trap_frame();
-        trap_frame.pc = elf.header.pt2.entry_point() as usize;
+        trap_frame.pc = program.entry;
     }
-}
\ No newline at end of file
+}
+
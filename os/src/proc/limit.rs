@@ -0,0 +1,60 @@
+use sys::syscall::{ProcStat, RLIMIT_COUNT, RLIMIT_HANDLES, RLIMIT_INFINITY};
+
+use super::{pid::find_process_from, Process};
+
+/**
+ * Limits on the resources of a process (RLIMIT_*).
+ * A forked child inherits them, and they are kept across exec.
+ */
+#[derive(Clone, Copy)]
+pub struct Limits([usize; RLIMIT_COUNT]);
+
+impl Limits {
+    pub const fn new() -> Self {
+        return Self([RLIMIT_INFINITY; RLIMIT_COUNT]);
+    }
+
+    /** The limit of %resource, or None if invalid. */
+    pub fn get(&self, resource : usize) -> Option<usize> {
+        return self.0.get(resource).copied();
+    }
+
+    /** Set the limit of %resource. Return the old one, or None if invalid. */
+    pub fn set(&mut self, resource : usize, limit : usize) -> Option<usize> {
+        let slot = self.0.get_mut(resource)?;
+        let old = *slot;
+        *slot = limit;
+        return Some(old);
+    }
+
+    /** Whether %usage of %resource is within the limit. */
+    pub fn allows(&self, resource : usize, usage : usize) -> bool {
+        return usage <= self.0[resource];
+    }
+}
+
+impl Process {
+    /** Whether one more handle can be held. */
+    pub fn can_hold_handle(&mut self) -> bool {
+        let count = self.get_handles().count();
+        return self.get_limits().allows(RLIMIT_HANDLES, count + 1);
+    }
+
+    unsafe fn get_stat(&mut self) -> ProcStat {
+        let handles = self.get_handles().count();
+        let memory  = self.get_memory_area();
+        return ProcStat {
+            resident    : memory.get_resident(),
+            private     : memory.count_private(),
+            heap        : memory.get_heap_size(),
+            stack       : memory.get_stack_size(),
+            handles,
+        };
+    }
+}
+
+/** Return the usage of the first process with pid no less than %pid, with its pid. */
+pub unsafe fn process_stat(pid : usize) -> Option<(usize, ProcStat)> {
+    let process = &mut *find_process_from(pid)?;
+    return Some((process.get_pid().bits(), process.get_stat()));
+}
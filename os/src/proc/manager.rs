@@ -1,5 +1,5 @@
 use core::ptr::null_mut;
-use crate::{alloc::{allocate_one_page, deallocate_one_page, PAGE_SIZE}, cpu::{current_cpu, CPU}, trap::Interrupt};
+use crate::{alloc::{allocate_one_page, deallocate_one_page, OutOfMemory, PAGE_SIZE}, cpu::{current_cpu, CPU}, trap::Interrupt};
 use super::{schedule::Schuduler, PidType, Process};
 extern crate alloc;
 use alloc::vec::Vec;
@@ -55,8 +55,24 @@ impl ProcessManager {
         self.schduler.unregister(process);
     }
 
-    pub unsafe fn insert_process(&mut self, process : Process) {
-        let real_process = &mut *POOL.add_process(process);
+    /** Store %process and start to run it. If out of memory, it is discarded. */
+    pub unsafe fn insert_process(&mut self, process : Process) -> Result<(), OutOfMemory> {
+        let process = self.store_process(process)?;
+        self.start_process(process);
+        return Ok(());
+    }
+
+    /**
+     * Store %process in the pool, but not to run yet.
+     * If out of memory, it is discarded.
+     */
+    pub unsafe fn store_process(&mut self, process : Process) -> Result<*mut Process, OutOfMemory> {
+        return POOL.add_process(process);
+    }
+
+    /** Start to run a process stored in the pool. */
+    pub unsafe fn start_process(&mut self, process : *mut Process) {
+        let real_process = &mut *process;
         PidType::register(real_process);
         self.insert_runnable(real_process);
         message!("New process created: {:?}", real_process.get_pid().bits());
@@ -108,9 +124,9 @@ pub unsafe fn run_process() {
 
 impl Block {
     const UNUSED : usize  = (1 << QLENGTH)- 1;
-    unsafe fn new() -> Block {
-        let storage = allocate_one_page() as *mut Process;
-        return Block { storage, unused : Self::UNUSED };
+    unsafe fn new() -> Result<Block, OutOfMemory> {
+        let storage = allocate_one_page()? as *mut Process;
+        return Ok(Block { storage, unused : Self::UNUSED });
     }
 
     unsafe fn free(&self) {
@@ -168,16 +184,23 @@ impl ProcessPool {
         return Self { process_pool : Vec::new(), };
     }
 
-    pub unsafe fn add_process(&mut self, process : Process) -> *mut Process {
+    /** Add %process to the pool. If out of memory, it is discarded. */
+    pub unsafe fn add_process(&mut self, process : Process) -> Result<*mut Process, OutOfMemory> {
         for block in self.process_pool.iter_mut() {
             if block.has_space() {
-                return block.add_process(process);
+                return Ok(block.add_process(process));
             }
         }
-        let mut block = Block::new();
+        let mut block = match Block::new() {
+            Ok(block)   => block,
+            Err(error)  => {
+                process.discard();
+                return Err(error);
+            },
+        };
         let result = block.add_process(process);
         self.process_pool.push(block);
-        return result;
+        return Ok(result);
     }
 
     pub unsafe fn remove_process(&mut self, process : *mut Process) {
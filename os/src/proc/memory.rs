@@ -1,6 +1,68 @@
-use core::cmp::max;
+use core::cmp::{max, min};
 
-use crate::{alloc::{PTEFlag, PageAddress, PAGE_SIZE}, trap::PageFaultType};
+use sys::{check::move_break, syscall::{RLIMIT_HEAP, RLIMIT_PAGES, RLIMIT_STACK}};
+
+use crate::{alloc::{count_free_pages, OutOfMemory, PTEFlag, PageAddress, PAGE_SIZE}, trap::PageFaultType};
+use super::Limits;
+
+extern crate alloc;
+use alloc::{collections::BTreeMap, vec::Vec};
+
+/**
+ * Pages lent by some requester, mapped until the request is responded
+ * (even if cancelled). They are referenced, so never freed under us.
+ */
+struct Lending {
+    handle  : usize,    // The request handle
+    start   : usize,    // Start of the window
+    count   : usize,    // Count of pages
+}
+
+/** Pages of some shared memory object, mapped into the window. */
+#[derive(Clone)]
+struct Sharing {
+    start   : usize,    // Start of the mapping
+    count   : usize,    // Count of pages
+}
+
+/** An anonymous mapping, whose pages are allocated on demand. */
+#[derive(Clone)]
+struct Region {
+    start   : usize,    // Start of the mapping
+    count   : usize,    // Count of pages
+    flag    : PTEFlag,  // Permission of the pages
+}
+
+impl Region {
+    fn finish(&self) -> usize { self.start + self.count * PAGE_SIZE }
+}
+
+/** A segment of the program, whose pages are filled on demand. */
+#[derive(Clone)]
+struct Segment {
+    image   : usize,            // Address of the whole program image
+    start   : usize,            // Start of the segment
+    finish  : usize,            // End of the segment in memory
+    data    : &'static [u8],    // Bytes in the image, followed by zeros
+    flag    : PTEFlag,          // Permission of the pages
+}
+
+impl Segment {
+    /** Copy the bytes of the segment within %virt into %page. */
+    fn fill(&self, page : PageAddress, virt : usize) {
+        let begin   = max(virt, self.start);
+        let end     = min(virt + PAGE_SIZE, self.start + self.data.len());
+        if begin >= end { return; }
+        page.copy_at(begin - virt, &self.data[begin - self.start..end - self.start]);
+    }
+}
+
+/**
+ * Read-only pages of program images, shared by all the processes
+ * running the same program, indexed by (image, address). The images
+ * are embedded in the kernel, so the pages are kept forever.
+ */
+static mut IMAGE_PAGES : BTreeMap<(usize, usize), PageAddress> = BTreeMap::new();
 
 pub struct MemoryArea {
     root            : PageAddress,  // root page table
@@ -8,22 +70,135 @@ pub struct MemoryArea {
     program_finish  : usize,        // End of program data, start of heap
     break_finish    : usize,        // End of heap
     stack_bottom    : usize,        // Bottom of stack
+    lending         : Vec<Lending>, // Lent pages, sorted by address
+    sharing         : Vec<Sharing>, // Shared pages, sorted by address
+    regions         : Vec<Region>,  // Anonymous mappings, sorted by address
+    segments        : Vec<Segment>, // Segments of the program
+    resident        : usize,        // Pages mapped, but for lent ones
 }
 
 const USER_STACK : usize = 1 << 38;
 const USER_STACK_LOWEST : usize = USER_STACK - PAGE_SIZE * 512;
 
+const USER_LEND : usize = 1 << 37;
+const USER_LEND_LIMIT : usize = USER_LEND + PAGE_SIZE * 512 * 16;
+
+const USER_SHARE : usize = 1 << 36;
+const USER_SHARE_LIMIT : usize = USER_LEND;
+
+const USER_MMAP : usize = 1 << 35;
+const USER_MMAP_LIMIT : usize = USER_SHARE;
+
+/**
+ * First fit of %size bytes in [%base, %limit), among the sorted %spans
+ * of (start, count of pages). Return the address and the index to insert.
+ */
+fn first_fit(spans : impl Iterator<Item = (usize, usize)>, base : usize, limit : usize, size : usize) -> Option<(usize, usize)> {
+    let mut start = base;
+    let mut index = 0;
+    for (begin, count) in spans {
+        if start + size <= begin { break; }
+        start = begin + count * PAGE_SIZE;
+        index += 1;
+    }
+    if start + size > limit { return None; }
+    return Some((start, index));
+}
+
 impl MemoryArea {
-    pub fn new() -> MemoryArea {
-        let root = PageAddress::new_pagetable();
-        unsafe { root.map_trampoline(); }
-        MemoryArea {
+    pub fn new() -> Result<MemoryArea, OutOfMemory> {
+        let root = PageAddress::new_pagetable()?;
+        if let Err(error) = unsafe { root.map_trampoline() } {
+            unsafe { root.free(); }
+            return Err(error);
+        }
+        Ok(MemoryArea {
             root,
             program_start   : 0,
             program_finish  : 0,
             break_finish    : 0,
             stack_bottom    : USER_STACK,
+            lending         : Vec::new(),
+            sharing         : Vec::new(),
+            regions         : Vec::new(),
+            segments        : Vec::new(),
+            resident        : 0,
+        })
+    }
+
+    /**
+     * Copy the address space of %parent for a forked child.
+     * Lent pages are not inherited, while shared pages stay shared.
+     * If out of memory, the child should be dropped.
+     */
+    pub unsafe fn fork_from(&mut self, parent : &MemoryArea) -> Result<(), OutOfMemory> {
+        self.get_satp().copy_from(parent.get_satp())?;
+        for lending in parent.lending.iter() {
+            self.get_satp().lend_unumap(lending.start, lending.count);
+        }
+        self.program_start  = parent.program_start;
+        self.program_finish = parent.program_finish;
+        self.break_finish   = parent.break_finish;
+        self.stack_bottom   = parent.stack_bottom;
+        self.sharing        = parent.sharing.clone();
+        self.regions        = parent.regions.clone();
+        self.segments       = parent.segments.clone();
+        self.resident       = parent.resident;
+        return Ok(());
+    }
+
+    /**
+     * Record a segment of %image at %start, holding %data and then zeros
+     * up to %size bytes. Its pages are filled on the first fault.
+     */
+    pub(super) fn add_segment(&mut self, image : &'static [u8], start : usize, size : usize, data : &'static [u8], flag : PTEFlag) {
+        let image = image.as_ptr() as usize;
+        self.segments.push(Segment { image, start, finish : start + size, data, flag });
+    }
+
+    /**
+     * Fill the page at %virt from the segments on the first fault.
+     * Read-only pages are shared with others running the same program.
+     * Return false if no segment is there, or access denied.
+     */
+    unsafe fn load_segment(&mut self, virt : usize, tp : &PageFaultType, limits : &Limits) -> Result<bool, OutOfMemory> {
+        let virt = virt & !(PAGE_SIZE - 1);
+        let segments : Vec<&Segment> = self.segments.iter()
+            .filter(|x| x.start < virt + PAGE_SIZE && virt < x.finish).collect();
+        if segments.is_empty() { return Ok(false); }
+
+        // Segments may share a page, so take all the permissions.
+        let flag = segments.iter().fold(PTEFlag::EMPTY, |x, y| x | y.flag);
+        let allowed = match tp {
+            PageFaultType::Load         => flag.contains(PTEFlag::RO),
+            PageFaultType::Store        => flag.contains(PTEFlag::WO),
+            PageFaultType::Instruction  => flag.contains(PTEFlag::XO),
+        };
+        if !allowed { return Ok(false); }
+
+        let root = self.get_satp();
+        if *tp == PageFaultType::Store && root.copy_on_write(virt)? { return Ok(true); }
+        if root.is_umapped(virt) { return Ok(false); }
+        if !self.has_room(limits, 1) { return Ok(false); }
+
+        if flag.contains(PTEFlag::WO) {
+            let page = root.new_umap(virt, flag)?;
+            for segment in segments.iter() { segment.fill(page, virt); }
+        } else {
+            let key  = (segments[0].image, virt);
+            let page = match IMAGE_PAGES.get(&key) {
+                Some(page) => *page,
+                None => {
+                    let page = PageAddress::new_shared()?;
+                    for segment in segments.iter() { segment.fill(page, virt); }
+                    IMAGE_PAGES.insert(key, page);
+                    page
+                },
+            };
+            root.share_umap(virt, &[page], flag)?;
         }
+        self.resident += 1;
+        return Ok(true);
     }
 
     pub(super) fn get_satp(&self) -> PageAddress {
@@ -37,59 +212,307 @@ impl MemoryArea {
         return end;
     }
 
-    pub unsafe fn sbrk(&mut self, increment: isize) -> usize {
+    /**
+     * Move the break by %increment, and return the old one.
+     * New pages are allocated on the first touch, in handle_page_fault.
+     * Return None if growing more than the free memory, or the limits,
+     * or if the break would go out of the heap.
+     */
+    pub unsafe fn sbrk(&mut self, increment: isize, limits : &Limits) -> Option<usize> {
         let old_break = self.break_finish;
-        let new_break = old_break + increment as usize;
+        let new_break = move_break(old_break, increment, self.program_finish, USER_MMAP)?;
         if increment > 0 {
+            if increment as usize / PAGE_SIZE >= count_free_pages() { return None; }
+            if !limits.allows(RLIMIT_HEAP, new_break - self.program_finish) { return None; }
+            if !self.has_room(limits, increment as usize / PAGE_SIZE) { return None; }
             self.break_finish = new_break;
-
-            let root = self.get_satp();
-            let old_page = (old_break - 1) / PAGE_SIZE;
-            let new_page = (new_break - 1) / PAGE_SIZE;
-            for page in (old_page + 1)..=new_page {
-                root.try_umap(page * PAGE_SIZE, PTEFlag::RW);
-            }
         } else if increment < 0 {
-            let new_break = max(new_break, self.program_finish);
             self.break_finish = new_break;
 
             let root = self.get_satp();
             let old_page = (old_break - 1) / PAGE_SIZE;
             let new_page = (new_break - 1) / PAGE_SIZE;
             for page in (new_page + 1)..=old_page {
-                root.try_unumap(page * PAGE_SIZE);
+                if root.try_unumap(page * PAGE_SIZE) { self.resident -= 1; }
             }
         }
-        return old_break;
+        return Some(old_break);
     }
 
     pub const fn get_user_stack_top() -> usize {
         return USER_STACK;
     }
 
-    pub unsafe fn add_stack(&mut self, size: usize) {
-        let stack_top = self.stack_bottom;
-        let stack_low = stack_top - size * PAGE_SIZE;
+    /** Programs must be loaded below the windows of mappings. */
+    pub const fn get_program_limit() -> usize {
+        return USER_MMAP;
+    }
+
+    /** Grow the stack downwards by %size pages. */
+    pub unsafe fn add_stack(&mut self, size: usize) -> Result<(), OutOfMemory> {
+        let root = self.get_satp();
+        for _ in 0..size {
+            let page = self.stack_bottom - PAGE_SIZE;
+            root.try_umap(page, PTEFlag::RW)?;
+            self.stack_bottom = page;
+            self.resident += 1;
+        }
+        return Ok(());
+    }
+
+    /**
+     * Map the pages of [src, src + len) in %root into the lending window.
+     * Return the address of src in the window, or None if the window is full
+     * (or out of memory for the page tables).
+     */
+    pub unsafe fn lend_from(&mut self, handle : usize, root : PageAddress, src : usize, len : usize) -> Option<usize> {
+        let offset  = src % PAGE_SIZE;
+        let count   = (offset + len + PAGE_SIZE - 1) / PAGE_SIZE;
+        let size    = count * PAGE_SIZE;
+
+        // First fit, since the lending list is sorted.
+        let spans = self.lending.iter().map(|x| (x.start, x.count));
+        let (start, index) = first_fit(spans, USER_LEND, USER_LEND_LIMIT, size)?;
+
+        if !self.get_satp().lend_umap(start, root, src - offset, count).ok()? { return None; }
+        self.lending.insert(index, Lending { handle, start, count });
+        return Some(start + offset);
+    }
+
+    /** Tear down the pages lent by the request of given handle. */
+    pub unsafe fn unlend(&mut self, handle : usize) {
+        let position = self.lending.iter().position(|x| x.handle == handle);
+        if let Some(index) = position {
+            let lending = self.lending.remove(index);
+            self.get_satp().lend_unumap(lending.start, lending.count);
+        }
+    }
+
+    /**
+     * Map the shared %pages into the sharing window with %flag.
+     * Return the address, or None if the window is full, out of memory,
+     * or beyond the limit of pages.
+     */
+    pub unsafe fn share_map(&mut self, pages : &[PageAddress], flag : PTEFlag, limits : &Limits) -> Option<usize> {
+        let count   = pages.len();
+        if !self.has_room(limits, count) { return None; }
+        let spans   = self.sharing.iter().map(|x| (x.start, x.count));
+        let (start, index) = first_fit(spans, USER_SHARE, USER_SHARE_LIMIT, count * PAGE_SIZE)?;
+
+        self.get_satp().share_umap(start, pages, flag).ok()?;
+        self.sharing.insert(index, Sharing { start, count });
+        self.resident += count;
+        return Some(start);
+    }
+
+    /** Unmap the shared pages mapped at %start. Return false if none. */
+    pub unsafe fn share_unmap(&mut self, start : usize) -> bool {
+        match self.sharing.iter().position(|x| x.start == start) {
+            Some(index) => {
+                let sharing = self.sharing.remove(index);
+                self.get_satp().share_unumap(sharing.start, sharing.count);
+                self.resident -= sharing.count;
+                return true;
+            },
+            None => return false,
+        }
+    }
+
+    /**
+     * Map %len bytes of anonymous memory with %flag, at %hint if it is
+     * free, or anywhere in the window. Pages are allocated on demand.
+     * Return the address, or None if no space, or beyond the limit of pages.
+     */
+    pub unsafe fn mmap(&mut self, hint : usize, len : usize, flag : PTEFlag, limits : &Limits) -> Option<usize> {
+        if len == 0 || len > USER_MMAP_LIMIT - USER_MMAP { return None; }
+        let count = (len + PAGE_SIZE - 1) / PAGE_SIZE;
+        if !self.has_room(limits, count) { return None; }
+        let size  = count * PAGE_SIZE;
+        let fixed = hint % PAGE_SIZE == 0 && hint >= USER_MMAP && hint <= USER_MMAP_LIMIT - size
+            && self.regions.iter().all(|x| x.finish() <= hint || hint + size <= x.start);
+
+        let start = if fixed {
+            hint
+        } else {
+            let spans = self.regions.iter().map(|x| (x.start, x.count));
+            first_fit(spans, USER_MMAP, USER_MMAP_LIMIT, size)?.0
+        };
+        let index = self.regions.iter().position(|x| x.start > start).unwrap_or(self.regions.len());
+        self.regions.insert(index, Region { start, count, flag });
+        return Some(start);
+    }
+
+    /**
+     * Unmap the pages in [%start, %start + %len), freeing those allocated.
+     * Return false if the range is not page-aligned in the window.
+     */
+    pub unsafe fn munmap(&mut self, start : usize, len : usize) -> bool {
+        let finish = match self.check_range(start, len) {
+            Some(finish) => finish,
+            None => return false,
+        };
+        self.split_regions(start, finish);
+
+        let root = self.get_satp();
+        let mut freed = 0;
+        self.regions.retain(|x| {
+            if x.start < start || x.start >= finish { return true; }
+            for page in 0..x.count {
+                if root.try_unumap(x.start + page * PAGE_SIZE) { freed += 1; }
+            }
+            return false;
+        });
+        self.resident -= freed;
+        return true;
+    }
+
+    /**
+     * Change the permission of the pages in [%start, %start + %len) to %flag.
+     * Return false if some of them are not mapped.
+     */
+    pub unsafe fn mprotect(&mut self, start : usize, len : usize, flag : PTEFlag) -> bool {
+        let finish = match self.check_range(start, len) {
+            Some(finish) => finish,
+            None => return false,
+        };
 
+        // The range must be covered without holes.
+        let mut covered = start;
+        for region in self.regions.iter() {
+            if region.start <= covered && covered < region.finish() { covered = region.finish(); }
+        }
+        if covered < finish { return false; }
+
+        self.split_regions(start, finish);
         let root = self.get_satp();
-        for page in (stack_low / PAGE_SIZE)..(stack_top / PAGE_SIZE) {
-            root.try_umap(page * PAGE_SIZE, PTEFlag::RW);
+        for region in self.regions.iter_mut() {
+            if region.start < start || region.start >= finish { continue; }
+            region.flag = flag;
+            for page in 0..region.count { root.protect(region.start + page * PAGE_SIZE, flag); }
+        }
+        return true;
+    }
+
+    /** Return the end of a page-aligned range in the window. */
+    fn check_range(&self, start : usize, len : usize) -> Option<usize> {
+        let finish = start.checked_add(len)?;
+        if start % PAGE_SIZE != 0 || len == 0 { return None; }
+        if start < USER_MMAP || finish > USER_MMAP_LIMIT { return None; }
+        return Some((finish + PAGE_SIZE - 1) & !(PAGE_SIZE - 1));
+    }
+
+    /** Split the regions, so that none crosses %start or %finish. */
+    fn split_regions(&mut self, start : usize, finish : usize) {
+        for point in [start, finish] {
+            let found = self.regions.iter().position(|x| x.start < point && point < x.finish());
+            if let Some(index) = found {
+                let region = &mut self.regions[index];
+                let count  = (point - region.start) / PAGE_SIZE;
+                let rest   = Region { start : point, count : region.count - count, flag : region.flag };
+                region.count = count;
+                self.regions.insert(index + 1, rest);
+            }
         }
     }
 
+    /** The permission of the anonymous mapping at %addr, if any. */
+    fn region_flag(&self, addr : usize) -> Option<PTEFlag> {
+        let region = self.regions.iter().find(|x| x.start <= addr && addr < x.finish())?;
+        return Some(region.flag);
+    }
+
     pub unsafe fn free(&self) {
         let root = self.get_satp();
         root.free();
     }
 
-    pub unsafe fn handle_page_fault(&mut self, addr : usize, tp : PageFaultType) -> bool {
+    /**
+     * Map the page at %addr on a fault. Return false if the access
+     * is invalid or beyond the limits, or OutOfMemory if no page is
+     * left for it.
+     */
+    pub unsafe fn handle_page_fault(&mut self, addr : usize, tp : PageFaultType, limits : &Limits) -> Result<bool, OutOfMemory> {
+        if self.load_segment(addr, &tp, limits)? { return Ok(true); }
+        if let Some(flag) = self.region_flag(addr) {
+            let allowed = match tp {
+                PageFaultType::Load         => flag.contains(PTEFlag::RO),
+                PageFaultType::Store        => flag.contains(PTEFlag::WO),
+                PageFaultType::Instruction  => flag.contains(PTEFlag::XO),
+            };
+            if !allowed { return Ok(false); }
+
+            let root = self.get_satp();
+            let page = addr & !(PAGE_SIZE - 1);
+            if tp == PageFaultType::Store && root.copy_on_write(page)? { return Ok(true); }
+            if root.is_umapped(page) { return Ok(false); }
+            if !self.has_room(limits, 1) { return Ok(false); }
+            root.new_umap(page, flag)?;
+            self.resident += 1;
+            return Ok(true);
+        }
+        if tp == PageFaultType::Store && self.get_satp().copy_on_write(addr)? {
+            return Ok(true);
+        }
+        let break_limit = (self.break_finish + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        if addr >= self.program_finish && addr < break_limit &&
+            (tp == PageFaultType::Load || tp == PageFaultType::Store) {
+            let root = self.get_satp();
+            let page = addr & !(PAGE_SIZE - 1);
+            if root.is_umapped(page) { return Ok(false); }
+            if !self.has_room(limits, 1) { return Ok(false); }
+            root.new_umap(page, PTEFlag::RW)?;
+            self.resident += 1;
+            return Ok(true);
+        }
         if addr < self.stack_bottom && addr >= USER_STACK_LOWEST &&
-            tp == PageFaultType::Load || tp == PageFaultType::Store {
+            (tp == PageFaultType::Load || tp == PageFaultType::Store) {
             let required = addr / PAGE_SIZE;
             let current  = self.stack_bottom / PAGE_SIZE;
-            self.add_stack(current - required);
-            return true;
+            if !limits.allows(RLIMIT_STACK, USER_STACK - required * PAGE_SIZE) {
+                warning!("Stack limit exceeded at 0x{:x}", addr);
+                return Ok(false);
+            }
+            if !self.has_room(limits, current - required) { return Ok(false); }
+            self.add_stack(current - required)?;
+            return Ok(true);
         }
+        return Ok(false);
+    }
+
+    /**
+     * Drop the user pages at once, e.g. for a process to be killed.
+     * The page tables are kept until it is freed.
+     */
+    pub unsafe fn release(&mut self) {
+        self.get_satp().release();
+        self.sharing.clear();
+        self.regions.clear();
+        self.resident = 0;
+    }
+
+    /** Whether %count more pages are within the limit of resident pages. */
+    fn has_room(&self, limits : &Limits, count : usize) -> bool {
+        if limits.allows(RLIMIT_PAGES, self.resident + count) { return true; }
+        warning!("Resident page limit exceeded");
         return false;
     }
+
+    /** Count the pages mapped, including those shared with others. */
+    pub fn get_resident(&self) -> usize {
+        return self.resident;
+    }
+
+    pub fn get_heap_size(&self) -> usize {
+        return self.break_finish - self.program_finish;
+    }
+
+    pub fn get_stack_size(&self) -> usize {
+        return USER_STACK - self.stack_bottom;
+    }
+
+    /** Count the pages freed if this is dropped, but for page tables. */
+    pub unsafe fn count_private(&self) -> usize {
+        return self.get_satp().count_private();
+    }
 }
+
@@ -7,25 +7,37 @@ mod memory;
 mod context;
 mod manager;
 mod schedule;
+mod oom;
+mod limit;
 
 pub use cpu::*;
 pub use proc::{Process, ProcessStatus};
-pub use pid::PidType;
+pub use pid::{for_each_process, PidType};
 pub use manager::run_process;
+pub use oom::set_oom_policy;
+pub use limit::{process_stat, Limits};
 
 use context::Context;
 use manager::ProcessManager;
 
+use sys::syscall::{CAP_RECEIVE, CAP_SEND, PM_PORT};
+
 use crate::alloc::{PTEFlag, KERNEL_SATP, PAGE_SIZE};
 
 pub unsafe fn init_process() {
     // Add trampoline to the page table
-    KERNEL_SATP.map_trampoline();
-    KERNEL_SATP.new_smap((PAGE_SIZE * 3).wrapping_neg(), PTEFlag::RW);
-    KERNEL_SATP.new_smap((PAGE_SIZE * 4).wrapping_neg(), PTEFlag::RW);
+    KERNEL_SATP.map_trampoline().expect("Out of memory!");
+    KERNEL_SATP.new_smap((PAGE_SIZE * 3).wrapping_neg(), PTEFlag::RW).expect("Out of memory!");
+    KERNEL_SATP.new_smap((PAGE_SIZE * 4).wrapping_neg(), PTEFlag::RW).expect("Out of memory!");
 
     let manager = current_cpu().get_manager();
 
-    manager.insert_process(Process::new_test(0));
-    manager.insert_process(Process::new_test(1));
+    // The process manager serves PM_PORT, and others may request it.
+    let mut pm = Process::new_test(0).expect("Out of memory!");
+    pm.get_caps().grant(PM_PORT, CAP_SEND | CAP_RECEIVE);
+    let mut sh = Process::new_test(1).expect("Out of memory!");
+    sh.get_caps().grant(PM_PORT, CAP_SEND);
+
+    manager.insert_process(pm).expect("Out of memory!");
+    manager.insert_process(sh).expect("Out of memory!");
 }
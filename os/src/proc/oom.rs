@@ -0,0 +1,63 @@
+use sys::syscall::{CAP_RECEIVE, OOM_KILL_FAULTING, OOM_KILL_LARGEST, PM_PORT};
+
+use super::{pid::for_each_process, Process, ProcessStatus};
+
+/** How to choose the process to kill when out of memory (OOM_KILL_*). */
+static mut OOM_POLICY : usize = OOM_KILL_FAULTING;
+
+/** Set the policy (OOM_KILL_*). Return the old one, or None if invalid. */
+pub unsafe fn set_oom_policy(policy : usize) -> Option<usize> {
+    if policy != OOM_KILL_FAULTING && policy != OOM_KILL_LARGEST { return None; }
+    let old = OOM_POLICY;
+    OOM_POLICY = policy;
+    return Some(old);
+}
+
+/**
+ * The process with the most private pages, among %current and those
+ * about to return to user. The process manager is never chosen.
+ */
+unsafe fn find_largest(current : &mut Process) -> *mut Process {
+    let mut victim : *mut Process = current;
+    let mut most = current.get_memory_area().count_private();
+    for_each_process(|process| {
+        if !process.has_status(ProcessStatus::RUNNABLE) || process.is_doomed() { return; }
+        if process.get_caps().has(PM_PORT, CAP_RECEIVE) { return; }
+        let count = process.get_memory_area().count_private();
+        if count > most { most = count; victim = process as *mut Process; }
+    });
+    return victim;
+}
+
+impl Process {
+    /**
+     * Make room for this process, out of memory, by the policy.
+     * Either it is killed, or some victim is doomed and waited for
+     * until its pages are dropped. Then the caller may try again.
+     */
+    pub unsafe fn handle_out_of_memory(&mut self) {
+        let victim = match OOM_POLICY {
+            OOM_KILL_LARGEST => find_largest(self),
+            _ => self as *mut Process,
+        };
+        if victim == self as *mut Process { self.handle_doom(); }
+
+        let pid = (*victim).get_pid();
+        warning!("Out of memory: process {} killed for {}", pid.bits(), self.get_pid().bits());
+        (*victim).doom();
+
+        // Stop waiting if it blocks before returning to user.
+        while let Some(victim) = pid.try_to_process() {
+            let victim = &mut *victim;
+            if !victim.is_doomed() || !victim.has_status(ProcessStatus::RUNNABLE) { break; }
+            self.yield_to_scheduler();
+        }
+    }
+
+    /** Drop the pages of a doomed process at once, and kill it. */
+    pub unsafe fn handle_doom(&mut self) -> ! {
+        self.get_memory_area().release();
+        self.undoom();
+        self.handle_fatal_error("killed for out of memory");
+    }
+}
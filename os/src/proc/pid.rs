@@ -26,6 +26,10 @@ impl PidType {
         assert!(!process.is_null());
         return process;
     }
+    /** Return the process, or None if it has gone. */
+    pub unsafe fn try_to_process(&self) -> Option<* mut Process> {
+        return PID_MAP.get(&self.bits()).copied();
+    }
     pub unsafe fn register(process : &mut Process) {
         register_process(process);
     }
@@ -52,6 +56,16 @@ unsafe fn unregister_process(process : &mut Process) {
     assert!(result.is_some(), "PID {} is not registered", process.get_pid().bits());
 }
 
+/** Call %func on each registered process. */
+pub unsafe fn for_each_process(mut func : impl FnMut(&mut Process)) {
+    for process in PID_MAP.values() { func(&mut **process); }
+}
+
+/** The first registered process with pid no less than %pid. */
+pub(super) unsafe fn find_process_from(pid : usize) -> Option<* mut Process> {
+    return PID_MAP.range(pid..).next().map(|(_, process)| *process);
+}
+
 /** Get the process from the pid map. */
 unsafe fn pid_to_process(pid : &PidType) -> * mut Process {
     return *PID_MAP.get(&pid.bits()).unwrap();
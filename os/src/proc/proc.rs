@@ -1,11 +1,15 @@
 extern crate alloc;
+use alloc::collections::VecDeque;
 
-use crate::alloc::PageAddress;
+use sys::syscall::{SERVICE_ASYNC_LIMIT, TICKET_LIMIT};
+
+use crate::alloc::{OutOfMemory, PageAddress};
+use crate::driver::timer::get_time;
 use crate::proc::current_cpu;
-use crate::service::Argument;
+use crate::service::{Argument, CapTable, HandleTable};
 use crate::trap::TrapFrame;
 use super::memory::MemoryArea;
-use super::{Context, PidType};
+use super::{Context, Limits, PidType};
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum ProcessStatus {
@@ -13,6 +17,7 @@ pub enum ProcessStatus {
     RUNNABLE,   // ready to run, but not running
     SERVING,    // serving some service
     SERVICE,    // waiting for some service
+    POLLING,    // waiting for async responses
 }
 
 pub struct Process {
@@ -22,43 +27,83 @@ pub struct Process {
     trap_frame  : * mut TrapFrame,  // trap frame
     context     : Context,          // current context
     response    : Option<Argument>, // response from service
+    receive     : [usize; 2],       // buffer to receive response
+    ticket      : usize,            // last ticket of async request
+    completion  : VecDeque<(usize, Argument)>, // async responses
+    pending     : usize,            // async requests not taken yet
+    deadline    : usize,            // deadline of blocking IPC (0 if none)
+    caps        : CapTable,         // rights on the ports
+    handles     : HandleTable,      // kernel objects held
+    limits      : Limits,           // limits on the resources
     priority    : u16,              // priority
+    boost       : u16,              // priority inherited from clients
     timing      : usize,            // timing
+    doomed      : bool,             // killed on return to user
 }
 
 impl Process {
     /** Initialize those necessary resources first. */
-    pub unsafe fn init() -> Process {
-        let memory  = MemoryArea::new();
+    pub unsafe fn init() -> Result<Process, OutOfMemory> {
+        let memory  = MemoryArea::new()?;
         let root    = memory.get_satp();
         message!("Process created with root {:#x}", root.address() as usize);
 
-        let (trap_frame, kernel_stack) = root.map_trap_frame();
+        let (trap_frame, kernel_stack) = match root.map_trap_frame() {
+            Ok(result) => result,
+            Err(error) => {
+                memory.free();
+                return Err(error);
+            },
+        };
 
         // Complete the resource initialization.
-        return Process {
+        return Ok(Process {
             status  : ProcessStatus::RUNNABLE,
             pid     : PidType::allocate(),
             context : Context::new_with(kernel_stack),
             response : None,
+            receive  : [0, 0],
+            ticket   : 0,
+            completion : VecDeque::new(),
+            pending  : 0,
+            deadline : 0,
+            caps     : CapTable::new(),
+            handles  : HandleTable::new(),
+            limits   : Limits::new(),
             priority : 1,
+            boost    : 0,
             timing   : 0,
+            doomed   : false,
             memory, trap_frame
-        };
+        });
     }
 
-    pub(super) unsafe fn reinit(&mut self) {
-        self.get_memory_area().free();
-        self.get_trap_frame().free();
-
-        self.memory = MemoryArea::new();
-        let root    = self.memory.get_satp();
+    /**
+     * Replace the memory with a new one, keeping the kernel stack in use.
+     * If out of memory, the process is left untouched.
+     */
+    pub(super) unsafe fn reinit(&mut self) -> Result<(), OutOfMemory> {
+        let kernel_stack = self.get_trap_frame().get_kernel_stack();
+        let memory  = MemoryArea::new()?;
+        let root    = memory.get_satp();
+        let trap_frame = match root.map_trap_frame_with(kernel_stack) {
+            Ok(trap_frame) => trap_frame,
+            Err(error) => {
+                memory.free();
+                return Err(error);
+            },
+        };
         message!("Process re-created with root {:#x}", root.address() as usize);
-        let (trap_frame, kernel_stack) = self.get_memory_area().get_satp().map_trap_frame();
+
+        self.get_memory_area().free();
+        self.memory     = memory;
         self.trap_frame = trap_frame;
         self.context    = Context::new_with(kernel_stack);
         self.response   = None;
+        self.pending   -= self.completion.len();
+        self.completion.clear();
         assert!(self.status == ProcessStatus::RUNNING);
+        return Ok(());
     }
 
     /** Return the inner context. */
@@ -78,7 +123,7 @@ impl Process {
         return self.status.clone();
     }
 
-    pub(super) fn has_status(&self, status : ProcessStatus) -> bool {
+    pub fn has_status(&self, status : ProcessStatus) -> bool {
         return self.status == status;
     }
 
@@ -94,6 +139,18 @@ impl Process {
         return &mut self.memory;
     }
 
+    pub fn get_caps(&mut self) -> &mut CapTable {
+        return &mut self.caps;
+    }
+
+    pub fn get_handles(&mut self) -> &mut HandleTable {
+        return &mut self.handles;
+    }
+
+    pub fn get_limits(&mut self) -> &mut Limits {
+        return &mut self.limits;
+    }
+
     /** Sleep and set the status as given. */
     pub fn sleep_as(&mut self, status : ProcessStatus) {
         assert_eq!(self.status, ProcessStatus::RUNNING, "Invalid to sleep!");
@@ -117,21 +174,103 @@ impl Process {
         return self.response.take();
     }
 
+    pub fn set_receive_buffer(&mut self, buffer : [usize; 2]) {
+        self.receive = buffer;
+    }
+
+    pub fn get_receive_buffer(&self) -> [usize; 2] {
+        return self.receive;
+    }
+
+    /**
+     * Return a new ticket for async request, which is never 0.
+     * Also used as the sequence of blocking requests.
+     */
+    pub fn new_ticket(&mut self) -> usize {
+        self.ticket = self.ticket % TICKET_LIMIT + 1;
+        return self.ticket;
+    }
+
+    /** Whether less than SERVICE_ASYNC_LIMIT responses are not taken. */
+    pub fn can_request_async(&self) -> bool {
+        return self.pending < SERVICE_ASYNC_LIMIT;
+    }
+
+    /** Return a new ticket for async request, counted until taken. */
+    pub fn new_async_ticket(&mut self) -> usize {
+        self.pending += 1;
+        return self.new_ticket();
+    }
+
+    pub fn push_completion(&mut self, ticket : usize, response : Argument) {
+        self.completion.push_back((ticket, response));
+    }
+
+    pub fn pop_completion(&mut self) -> Option<(usize, Argument)> {
+        core::hint::black_box(&self.completion);
+        let result = self.completion.pop_front();
+        if result.is_some() { self.pending -= 1; }
+        return result;
+    }
+
+    /** Set the deadline of the next blocking IPC, 0 for none. */
+    pub fn set_deadline(&mut self, deadline : usize) {
+        self.deadline = deadline;
+    }
+
+    pub fn get_deadline(&self) -> usize {
+        return self.deadline;
+    }
+
+    /** Whether the deadline has passed. */
+    pub fn is_expired(&self) -> bool {
+        return self.deadline != 0 && get_time() >= self.deadline;
+    }
+
     pub unsafe fn destroy(&mut self) {
         PidType::unregister(self);
 
         self.get_memory_area().free();
         self.get_trap_frame().free();
 
+        // Release the kernel objects held, e.g. shared memory.
+        *self.get_handles() = HandleTable::new();
+
         let _ = *self; // Drop the process.
     }
 
+    /** Drop a process never inserted, e.g. the child of a failed fork. */
+    pub unsafe fn discard(mut self) {
+        self.get_memory_area().free();
+        self.get_trap_frame().free();
+    }
+
+    /** Kill the process when it next returns to user. */
+    pub fn doom(&mut self) {
+        self.doomed = true;
+    }
+
+    /** The pages are dropped, so no one should wait for it. */
+    pub(super) fn undoom(&mut self) {
+        self.doomed = false;
+    }
+
+    pub fn is_doomed(&self) -> bool {
+        return self.doomed;
+    }
+
     pub fn set_priority(&mut self, priority : u16) {
         self.priority = priority;
     }
 
+    /** Return the effective priority, with inheritance. */
     pub fn get_priority(&self) -> usize {
-        return self.priority as usize;
+        return core::cmp::max(self.priority, self.boost) as usize;
+    }
+
+    /** Inherit the priority of clients served, 0 to drop. */
+    pub fn set_boost(&mut self, boost : usize) {
+        self.boost = boost as u16;
     }
 
     pub fn set_timing(&mut self, timing : usize) {
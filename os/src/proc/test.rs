@@ -1,6 +1,7 @@
 use core::ptr::addr_of;
 
-use super::{memory::MemoryArea, Process};
+use crate::alloc::OutOfMemory;
+use super::{elf::Program, memory::MemoryArea, Process};
 
 #[repr(C)]
 struct Property {
@@ -45,23 +46,36 @@ unsafe fn find_given_index(name : &[u8]) -> Option<usize> {
 
 
 impl Process {
-    pub(super) unsafe fn new_test(which : usize) -> Process {
+    /** Create a process running the built-in program %which. */
+    pub(super) unsafe fn new_test(which : usize) -> Result<Process, OutOfMemory> {
         let data = load_file(which);
+        let program = match Program::parse(data) {
+            Ok(program) => program,
+            Err(msg)    => panic!("Invalid program {}: {}", which, msg),
+        };
 
-        let mut process = Process::init();
+        let mut process = Process::init()?;
 
         // Initialize the text and data segment.
-        process.init_from_elf(data);
+        process.init_from_elf(&program);
 
         // Initialize the user stack.
         let trap_frame = process.get_trap_frame();
         trap_frame.sp = MemoryArea::get_user_stack_top();
 
-        process.get_memory_area().add_stack(1);
+        if let Err(error) = process.get_memory_area().add_stack(1) {
+            process.discard();
+            return Err(error);
+        }
 
-        return process;
+        return Ok(process);
     }
 
+    /**
+     * Replace the program with the one named %name. Return false if
+     * there is no such program, it is malformed, or out of memory,
+     * and then the process is left untouched.
+     */
     pub unsafe fn exec_test(&mut self, name : &[u8]) -> bool {
         match find_given_index(name) {
             None => {
@@ -69,16 +83,27 @@ impl Process {
             },
             Some(which) => {
                 let data = load_file(which);
+                let program = match Program::parse(data) {
+                    Ok(program) => program,
+                    Err(msg)    => {
+                        warning!("exec fails: {}", msg);
+                        return false;
+                    },
+                };
 
-                self.reinit();
+                if self.reinit().is_err() {
+                    warning!("exec fails: out of memory");
+                    return false;
+                }
 
-                self.init_from_elf(data);
+                self.init_from_elf(&program);
 
                 // Initialize the user stack.
                 let trap_frame = self.get_trap_frame();
                 trap_frame.sp = MemoryArea::get_user_stack_top();
 
-                self.get_memory_area().add_stack(1);
+                // Otherwise, the stack is mapped on the first fault.
+                let _ = self.get_memory_area().add_stack(1);
                 return true;
             }
         }
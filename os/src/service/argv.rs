@@ -1,13 +1,20 @@
 extern crate alloc;
 use alloc::{boxed::Box, vec::Vec};
-use sys::syscall::{ARGS_BUFFERED, ARGS_REGISTER};
+use core::cmp::min;
+use sys::syscall::{ARGS_BUFFERED, ARGS_CAPABILITY, ARGS_HANDLE, ARGS_MAPPING, ARGS_REGISTER, ARGS_UPOINTER, ARGS_WIDE, SERVICE_ERROR};
 
-use crate::{alloc::PTEFlag, proc::Process, utility::SliceIter};
+use crate::{alloc::{PTEFlag, PageAddress}, proc::Process, trap::TrapFrame, utility::SliceIter};
+
+use super::KernelObject;
 
 pub enum Argument {
     Register(usize, usize),     // In 2 registers.
     Buffered(Box<[u8]>),        // In a kernel buffer.
     Upointer(*mut u8, usize),   // In a user pointer
+    Mapping(*mut u8, usize),    // In user pages lent to the servant
+    Capability(usize, usize),   // Rights on a port, granted to the receiver
+    Handle(KernelObject),       // Moved into the handle table of the receiver
+    Wide([usize; 6]),           // In a0, a1 and t1 to t4.
 }
 
 fn create_sized_boxed(size : usize) -> Box<[u8]> {
@@ -30,6 +37,36 @@ impl Argument {
                 process.get_satp().user_to_core(SliceIter::new(&mut dst), buf, len);
                 Self::Buffered(dst)
             },
+            ARGS_UPOINTER => {
+                let buf = args[0];
+                let len = args[1];
+                process.address_check([buf, len], PTEFlag::RO);
+                Self::Upointer(buf as *mut u8, len)
+            },
+            ARGS_MAPPING => {
+                let buf = args[0];
+                let len = args[1];
+                process.address_check([buf, len], PTEFlag::RO);
+                Self::Mapping(buf as *mut u8, len)
+            },
+            ARGS_CAPABILITY => {
+                let port   = args[0];
+                let rights = args[1];
+                if !process.get_caps().has(port, rights) {
+                    process.handle_fatal_error("transferring rights not held");
+                }
+                Self::Capability(port, rights)
+            },
+            ARGS_WIDE => {
+                let trap_frame = process.get_trap_frame();
+                Self::Wide([args[0], args[1], trap_frame.t1, trap_frame.t2, trap_frame.t3, trap_frame.t4])
+            },
+            ARGS_HANDLE => {
+                match process.get_handles().take(args[0]) {
+                    Some(object) => Self::Handle(object),
+                    None => process.handle_fatal_error("transferring invalid handle"),
+                }
+            },
             _ => panic!("Invalid argument for syscall"),
         }
     }
@@ -37,8 +74,94 @@ impl Argument {
     pub unsafe fn get_register(&self) -> Option <(usize, usize)> {
         match self {
             Self::Register(a, b) => Some((*a, *b)),
+            Self::Wide(words)    => Some((words[0], words[1])),
             _ => None,
         }
     }
 
+    /** Bytes to be copied for the message (lent pages are not copied). */
+    pub fn get_length(&self) -> usize {
+        match self {
+            Self::Buffered(buffer)  => buffer.len(),
+            Self::Upointer(_, size) => *size,
+            _ => 0,
+        }
+    }
+
+    /**
+     * Grant the rights or handles to %target, if any.
+     * Return what %target sees, which is in registers.
+     */
+    pub unsafe fn grant_to(self, target : &mut Process) -> Self {
+        match self {
+            Self::Capability(port, rights) => {
+                target.get_caps().grant(port, rights);
+                Self::Register(port, rights)
+            },
+            Self::Handle(object) => {
+                let value = object.get_value();
+                Self::Register(object.install(target), value)
+            },
+            _ => self,
+        }
+    }
+
+    /**
+     * Copy a response from %source into the receive %buffer in %root.
+     * Return the register response seen by the receiver, which holds the
+     * length of response, or the negative length if it is truncated,
+     * or SERVICE_ERROR if the pages of %source or the buffer are gone.
+     * The buffer may have been lent since it was checked, and is then
+     * to be copied on write, so the private copies are made first.
+     */
+    pub unsafe fn into_response(self, root : PageAddress, buffer : [usize; 2], source : PageAddress) -> Self {
+        let [buf, cap] = buffer;
+        match self {
+            Self::Register(_, _) | Self::Wide(_) => self,
+            Self::Buffered(mut buffer) => {
+                let len = min(buffer.len(), cap);
+                if root.break_cow(buf, len).is_err() { return Self::Register(SERVICE_ERROR, 0); }
+                if !root.core_to_user(buf, len, SliceIter::new(&mut buffer)) {
+                    return Self::Register(SERVICE_ERROR, 0);
+                }
+                Self::Register(get_response_length(buffer.len(), cap), 0)
+            },
+            Self::Upointer(ptr, len) | Self::Mapping(ptr, len) => {
+                if root.break_cow(buf, min(len, cap)).is_err() { return Self::Register(SERVICE_ERROR, 0); }
+                if !root.user_to_user(buf, source, ptr as usize, min(len, cap)) {
+                    return Self::Register(SERVICE_ERROR, 0);
+                }
+                Self::Register(get_response_length(len, cap), 0)
+            },
+            Self::Capability(_, _) | Self::Handle(_) => unreachable!("Rights should have been granted"),
+        }
+    }
+
+    /** Copy those data in the user space of %source into a kernel buffer. */
+    pub unsafe fn into_kernel(self, source : PageAddress) -> Self {
+        match self {
+            Self::Upointer(ptr, len) | Self::Mapping(ptr, len) => {
+                let mut dst = create_sized_boxed(len);
+                source.user_to_core(SliceIter::new(&mut dst), ptr as usize, len);
+                Self::Buffered(dst)
+            },
+            _ => self,
+        }
+    }
+}
+
+/** Write a wide message into the registers. */
+pub fn set_wide(trap_frame : &mut TrapFrame, words : &[usize; 6]) {
+    trap_frame.a0 = words[0];
+    trap_frame.a1 = words[1];
+    trap_frame.t1 = words[2];
+    trap_frame.t2 = words[3];
+    trap_frame.t3 = words[4];
+    trap_frame.t4 = words[5];
+}
+
+/** Length of response, negative when the buffer is too small. */
+fn get_response_length(len : usize, cap : usize) -> usize {
+    if cap < len { return len.wrapping_neg(); }
+    return len;
 }
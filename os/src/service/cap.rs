@@ -0,0 +1,35 @@
+extern crate alloc;
+use alloc::collections::BTreeMap;
+
+/** Rights (CAP_SEND, CAP_RECEIVE) of a process on the ports. */
+#[derive(Clone)]
+pub struct CapTable(BTreeMap<usize, usize>);
+
+impl CapTable {
+    pub const fn new() -> Self {
+        return Self(BTreeMap::new());
+    }
+
+    /** Whether all the %rights on %port are held. */
+    pub fn has(&self, port : usize, rights : usize) -> bool {
+        return self.0.get(&port).map_or(0, |x| *x) & rights == rights;
+    }
+
+    pub fn grant(&mut self, port : usize, rights : usize) {
+        if rights == 0 { return; }
+        *self.0.entry(port).or_insert(0) |= rights;
+    }
+
+    /** Drop all the rights on %port, which is gone. */
+    pub fn revoke(&mut self, port : usize) {
+        self.0.remove(&port);
+    }
+
+    /** Keep only %rights on %port. */
+    pub fn restrict(&mut self, port : usize, rights : usize) {
+        if let Some(x) = self.0.get_mut(&port) {
+            *x &= rights;
+            if *x == 0 { self.0.remove(&port); }
+        }
+    }
+}
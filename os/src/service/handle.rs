@@ -8,20 +8,39 @@ impl ServiceHandle {
     pub(super) fn bits(&self) -> usize { return self.0; }
     pub(super) fn new_async() -> Self { return Self(0); }
     pub(super) fn is_async(&self) -> bool { return self.0 == 0; }
+    pub(super) fn is_from(&self, pid : usize) -> bool {
+        return !self.is_async() && handle_to_pid(self.0) == pid;
+    }
+    /** Priority of the requester, if it's blocked on the request. */
+    pub(super) unsafe fn get_priority(&self) -> usize {
+        if self.is_async() || self.get_ticket() != 0 { return 0; }
+        match self.try_to_process() {
+            Some(process)   => (*process).get_priority(),
+            None            => 0,
+        }
+    }
+    pub(super) fn get_ticket(&self) -> usize { return handle_to_ticket(self.0); }
     pub(super) unsafe fn to_process(self) -> *mut Process {
         return handle_to_process(self);
     }
-    pub(super) unsafe fn from_process(process : *mut Process) -> Self {
-        return process_to_handle(process);
+    pub(super) unsafe fn try_to_process(&self) -> Option<*mut Process> {
+        return PidType::new(handle_to_pid(self.0)).try_to_process();
+    }
+    /**
+     * Handle of a blocking request. The %sequence tells it from the former
+     * ones of the same process, so a late response never matches it.
+     */
+    pub(super) unsafe fn from_request(process : *mut Process, sequence : usize) -> Self {
+        let pid = &(*process).get_pid();
+        return Self::new(sequence_to_handle(pid.bits(), sequence));
+    }
+    pub(super) unsafe fn from_ticket(process : *mut Process, ticket : usize) -> Self {
+        let pid = &(*process).get_pid();
+        return Self::new(ticket_to_handle(pid.bits(), ticket));
     }
 }
 
-use sys::syscall::{pid_to_handle,handle_to_pid};
-
-unsafe fn process_to_handle(process : *mut Process) -> ServiceHandle {
-    let pid = &(*process).get_pid();
-    return ServiceHandle::new(pid_to_handle(pid.bits()));
-}
+use sys::syscall::{handle_to_pid,sequence_to_handle,ticket_to_handle,handle_to_ticket};
 
 unsafe fn handle_to_process(handle : ServiceHandle) -> *mut Process {
     let pid = PidType::new(handle_to_pid(handle.bits()));
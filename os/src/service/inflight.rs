@@ -0,0 +1,69 @@
+extern crate alloc;
+use alloc::{collections::BTreeMap, vec::Vec};
+
+/** A request that has been received, but not responded yet. */
+pub(super) struct Serving {
+    pub servant : usize,    // Pid of the servant
+    pub port    : usize,    // Where it was received
+    pub since   : usize,    // When it was requested
+}
+
+/**
+ * In-flight requests, indexed by handle. A handle holds a ticket or a
+ * sequence, so a late response to a cancelled one matches nothing.
+ */
+static mut INFLIGHT : BTreeMap<usize, Serving> = BTreeMap::new();
+
+/**
+ * Record that %servant has received the request of %handle at %port,
+ * which was requested at %since.
+ */
+pub(super) unsafe fn start(handle : usize, servant : usize, port : usize, since : usize) {
+    let result = INFLIGHT.insert(handle, Serving { servant, port, since });
+    assert!(result.is_none(), "Handle {:#x} is already in flight", handle);
+}
+
+/**
+ * Finish the request of %handle by %servant, and return the record.
+ * Return None if no such request (e.g. cancelled), or not the servant.
+ */
+pub(super) unsafe fn finish(handle : usize, servant : usize) -> Option<Serving> {
+    match INFLIGHT.get(&handle) {
+        Some(serving) if serving.servant == servant => {
+            return INFLIGHT.remove(&handle);
+        },
+        _ => return None,
+    }
+}
+
+/**
+ * Delegate the request of %handle to %servant, who should respond.
+ * Return the old servant, or None if no such request.
+ */
+pub(super) unsafe fn delegate(handle : usize, servant : usize) -> Option<usize> {
+    let serving = INFLIGHT.get_mut(&handle)?;
+    let old = serving.servant;
+    serving.servant = servant;
+    return Some(old);
+}
+
+/** Whether the request of %handle is being served by %servant. */
+pub(super) unsafe fn is_serving(handle : usize, servant : usize) -> bool {
+    return INFLIGHT.get(&handle).map_or(false, |x| x.servant == servant);
+}
+
+/** Return the handles and ports of those served by %servant. */
+pub(super) unsafe fn served_by(servant : usize) -> Vec<(usize, usize)> {
+    return INFLIGHT.iter().filter(|(_, y)| y.servant == servant).map(|(x, y)| (*x, y.port)).collect();
+}
+
+/** Return the servants of those requests received at %port. */
+pub(super) unsafe fn servants_at(port : usize) -> Vec<usize> {
+    return INFLIGHT.values().filter(|y| y.port == port).map(|y| y.servant).collect();
+}
+
+/** Remove all the requests which match %filter. */
+pub(super) unsafe fn cancel(filter : impl Fn(usize, &Serving) -> bool) -> Vec<(usize, Serving)> {
+    let handles : Vec<usize> = INFLIGHT.iter().filter(|(x, y)| filter(**x, y)).map(|(x, _)| *x).collect();
+    return handles.into_iter().map(|x| (x, INFLIGHT.remove(&x).unwrap())).collect();
+}
@@ -2,52 +2,421 @@ mod argv;
 mod handle;
 mod service;
 mod request;
+mod registry;
+mod inflight;
+mod timeout;
+mod cap;
+mod object;
+mod stats;
 
 extern crate alloc;
 
-pub use argv::Argument;
+pub use argv::{set_wide, Argument};
+pub use cap::CapTable;
+pub use object::{HandleTable, KernelObject};
 use handle::ServiceHandle;
-use request::Request;
+use request::{Forward, Request};
 use service::Service;
 
-use crate::proc::{Process, ProcessStatus};
+use core::{cmp::max, ptr::null_mut};
+use alloc::vec::Vec;
+use sys::syscall::{IpcEvent, IpcStat, CAP_RECEIVE, CAP_SEND, IPC_CANCEL, IPC_TRACE_OFF, IPC_TRACE_ON, SERVICE_DEAD, SERVICE_ERROR, SERVICE_TIMEOUT};
 
-const MAX_SERVICE : usize = 16;
-const ARRAY_REPEAT_VALUE: Service = Service::new();
-static mut SERVICE : [Service; MAX_SERVICE] = [ARRAY_REPEAT_VALUE; MAX_SERVICE];
+use crate::proc::{PidType, Process, ProcessStatus};
+
+/** Return the service at %port, which must have been registered. */
+unsafe fn get_service(port : usize) -> &'static mut Service {
+    return registry::get_service(port).expect("No such service");
+}
+
+/** Queue %request at %port, which must have been registered. */
+unsafe fn submit(port : usize, request : Request) {
+    let service = get_service(port);
+    let handle  = request.get_handle();
+    let bytes   = request.get_length();
+    service.push_back(request);
+    stats::on_request(port, handle, bytes, service.depth());
+}
+
+/**
+ * Return the counters of the first port no less than %port, with it.
+ * The queue depth is filled in only if the port is still there.
+ */
+pub unsafe fn service_stat(port : usize) -> Option<(usize, IpcStat)> {
+    let (port, mut stat) = stats::next_stat(port)?;
+    if let Some(service) = registry::get_service(port) {
+        stat.depth = service.depth();
+    }
+    return Some((port, stat));
+}
+
+/** Switch the trace by %flag (IPC_TRACE_*), and take at most %count events. */
+pub unsafe fn service_trace(flag : usize, count : usize) -> Vec<IpcEvent> {
+    match flag {
+        IPC_TRACE_ON    => stats::set_tracing(true),
+        IPC_TRACE_OFF   => stats::set_tracing(false),
+        _ => {},
+    }
+    return stats::take_trace(count);
+}
+
+/** Whether %port is a registered service. */
+pub unsafe fn service_exists(port : usize) -> bool {
+    return registry::get_service(port).is_some();
+}
+
+pub unsafe fn service_lookup(name : &[u8]) -> Option<usize> {
+    return registry::lookup(name);
+}
 
 impl Process {
+    /**
+     * Request and wait for the response. If there is a deadline,
+     * the response may be SERVICE_TIMEOUT when the deadline passes.
+     */
     pub unsafe fn service_request(&mut self, args : Argument, kind : usize, port : usize) {
-        let service = &mut SERVICE[port];
+        let service = get_service(port);
         self.sleep_as(ProcessStatus::SERVICE);
-        let handle = ServiceHandle::from_process(self);
-        service.push_back(Request::new(args, kind, handle));
+        let sequence = self.new_ticket();
+        let handle = ServiceHandle::from_request(self, sequence);
+        submit(port, Request::new(args, kind, handle));
+        self.enter_deadline();
+        for servant in inflight::servants_at(port) {
+            refresh_boost(servant);
+        }
 
         match service.try_wake_up_servant() {
             Some(process)   => self.yield_to_process(&mut *process),
             None            => self.yield_to_scheduler(),
         }
+        self.leave_deadline();
+    }
+
+    /**
+     * Receive a request on %port.
+     * Return false if the deadline passes before any request.
+     */
+    pub unsafe fn service_receive(&mut self, port : usize) -> bool {
+        return self.service_receive_any(&[port]).is_some();
+    }
+
+    /**
+     * Receive a request on any of %ports, the former preferred.
+     * Return the port it comes from, or None if the deadline passes.
+     */
+    pub unsafe fn service_receive_any(&mut self, ports : &[usize]) -> Option<usize> {
+        return self.receive_impl(ports, null_mut());
+    }
+
+    /**
+     * Receive a request on any of %ports. If it has to wait, switch
+     * to %handoff (if not null) directly, instead of the scheduler.
+     * Requests whose buffer is gone fail with SERVICE_ERROR, and the
+     * next one is received instead.
+     */
+    unsafe fn receive_impl(&mut self, ports : &[usize], mut handoff : *mut Process) -> Option<usize> {
+        self.enter_deadline();
+        let result = loop {
+            let port = match self.wait_for_request(ports, handoff) {
+                Some(port)  => port,
+                None        => break None,
+            };
+            handoff = null_mut();
+
+            let service = get_service(port);
+            let request = service.front_mut().expect("WTF no request!");
+            let handle  = ServiceHandle::new(request.get_handle());
+            match request.try_forward(self) {
+                Forward::Done => {
+                    if !handle.is_async() {
+                        inflight::start(handle.bits(), self.get_pid().bits(), port, request.get_since());
+                    }
+                    stats::on_receive(port, handle.bits());
+                    service.pop_front();
+                    refresh_boost(self.get_pid().bits());
+                    break Some(port);
+                },
+                Forward::Retry => break Some(port),
+                Forward::Failed => {
+                    stats::on_cancel(port, handle.bits());
+                    service.pop_front();
+                    fail_request(handle, SERVICE_ERROR);
+                },
+            }
+        };
+        self.leave_deadline();
+        return result;
+    }
+
+    /** Wait until any of %ports has some request. */
+    unsafe fn wait_for_request(&mut self, ports : &[usize], mut handoff : *mut Process) -> Option<usize> {
+        loop {
+            let found = ports.iter().find(|port| get_service(**port).has_request());
+            if let Some(port) = found { return Some(*port); }
+            if self.is_expired() { return None; }
+
+            // Queue up with other idle servants. When woken up, the
+            // request may have been taken by another servant already.
+            for port in ports { get_service(*port).add_servant(self); }
+            self.sleep_as(ProcessStatus::SERVING);
+            if handoff.is_null() {
+                self.yield_to_scheduler();
+            } else {
+                self.yield_to_process(&mut *handoff);
+                handoff = null_mut();
+            }
+            for port in ports { get_service(*port).remove_servant(self); }
+        }
+    }
+
+    unsafe fn enter_deadline(&mut self) {
+        if self.get_deadline() != 0 {
+            timeout::enter(self.get_pid().bits());
+        }
+    }
+
+    unsafe fn leave_deadline(&mut self) {
+        if self.get_deadline() != 0 {
+            timeout::leave(self.get_pid().bits());
+            self.set_deadline(0);
+        }
+    }
+
+    /**
+     * Send a request without blocking.
+     * Return the ticket, which is used to match the response later.
+     */
+    pub unsafe fn service_request_nonblock(&mut self, args : Argument, kind : usize, port : usize) -> usize {
+        let service = get_service(port);
+        let ticket  = self.new_async_ticket();
+        let handle  = ServiceHandle::from_ticket(self, ticket);
+        submit(port, Request::new(args, kind, handle));
+        service.try_wake_up_servant();
+        return ticket;
+    }
+
+    /**
+     * Take one response of the non-blocking requests.
+     * If %block, wait until some response arrives.
+     */
+    pub unsafe fn service_complete(&mut self, block : bool) -> Option<(usize, Argument)> {
+        loop {
+            let result = self.pop_completion();
+            if result.is_some() || !block { return result; }
+            self.sleep_as(ProcessStatus::POLLING);
+            self.yield_to_scheduler();
+        }
     }
 
-    pub unsafe fn service_receive(&mut self, port : usize) {
-        let service = &mut SERVICE[port];
-        let request = service.wait_for_request(self);
-        if request.try_forward(self) {
-            service.pop_front();
+    /**
+     * Whether this process may receive on %port.
+     * The first receiver of an unclaimed port becomes its owner.
+     */
+    pub unsafe fn service_can_receive(&self, port : usize) -> bool {
+        return registry::can_serve(port, self.get_pid().bits());
+    }
+
+    /**
+     * Claim a new port with %name. Return None if the name is taken.
+     * If %notify, servants are told when a request is cancelled.
+     */
+    pub unsafe fn service_register(&mut self, name : &[u8], notify : bool) -> Option<usize> {
+        let port = registry::register(name, self.get_pid().bits(), notify)?;
+        stats::reset(port);
+        self.get_caps().grant(port, CAP_SEND | CAP_RECEIVE);
+        return Some(port);
+    }
+
+    /**
+     * Make a local handle of the right to respond to %handle,
+     * so that the request can be delegated to another servant.
+     */
+    pub unsafe fn service_handle_reply(&mut self, handle : usize) -> Option<usize> {
+        if !inflight::is_serving(handle, self.get_pid().bits()) { return None; }
+        self.get_handles().remove_reply(handle);
+        return Some(self.get_handles().insert(KernelObject::Reply(handle)));
+    }
+
+    /** Release a port claimed by this process. */
+    pub unsafe fn service_unregister(&mut self, port : usize) -> bool {
+        return registry::unregister(port, self.get_pid().bits());
+    }
+
+    /** Cancel all the requests sent by this process, which is dying. */
+    pub unsafe fn service_cancel(&mut self) {
+        let pid = self.get_pid().bits();
+        cancel_requests(|x| ServiceHandle::new(x).is_from(pid));
+    }
+
+    /**
+     * Abandon all the services of this process, which is dying.
+     * Requests served by us, or pending at the ports we own, fail with
+     * SERVICE_DEAD, and those ports can be claimed by others again.
+     */
+    pub unsafe fn service_abandon(&mut self) {
+        let pid = self.get_pid().bits();
+        for (handle, _) in inflight::cancel(|_, serving| serving.servant == pid) {
+            fail_request(ServiceHandle::new(handle), SERVICE_DEAD);
+        }
+        for port in registry::owned_by(pid) {
+            for request in registry::release(port) {
+                fail_request(ServiceHandle::new(request.get_handle()), SERVICE_DEAD);
+            }
         }
     }
 
-    pub unsafe fn service_respond(&mut self, args : Argument, handle : usize) {
+    /**
+     * Respond to the request of %handle.
+     * Return false if the request is cancelled, or not served by us.
+     */
+    pub unsafe fn service_respond(&mut self, args : Argument, handle : usize) -> bool {
+        match self.respond_impl(args, handle) {
+            Some(target) => {
+                if !target.is_null() { self.yield_to_process(&mut *target); }
+                return true;
+            },
+            None => return false,
+        }
+    }
+
+    /**
+     * Respond to %handle, and then receive the next request on %port.
+     * When it has to wait, the client is switched to directly.
+     * Return whether responded, and whether received (not timed out).
+     */
+    pub unsafe fn service_reply_and_receive(&mut self, args : Argument, handle : usize, port : usize) -> (bool, bool) {
+        let target = self.respond_impl(args, handle);
+        let handoff = target.unwrap_or(null_mut());
+        let result = self.receive_impl(&[port], handoff);
+        return (target.is_some(), result.is_some());
+    }
+
+    /**
+     * Deliver the response to %handle without switching.
+     * Return the client blocked on it (null if async),
+     * or None if the request is cancelled, or not served by us.
+     */
+    unsafe fn respond_impl(&mut self, args : Argument, handle : usize) -> Option<*mut Process> {
+        // Done with the request anyway, even if it has been cancelled.
+        self.get_memory_area().unlend(handle);
+        self.get_handles().remove_reply(handle);
+        let serving = inflight::finish(handle, self.get_pid().bits())?;
+        stats::on_respond(serving.port, handle, args.get_length(), serving.since);
+        refresh_boost(self.get_pid().bits());
         let handle = ServiceHandle::new(handle);
+        let ticket = handle.get_ticket();
         let target = &mut *handle.to_process();
-        target.set_response(args);
+        let args   = args.grant_to(target);
+
+        if ticket != 0 {
+            // Non-blocking request, so the servant continues to run.
+            target.push_completion(ticket, args.into_kernel(self.get_satp()));
+            if target.has_status(ProcessStatus::POLLING) {
+                target.wake_up_from(ProcessStatus::POLLING);
+            }
+            return Some(null_mut());
+        }
+
+        let buffer = target.get_receive_buffer();
+        let response = args.into_response(target.get_satp(), buffer, self.get_satp());
+        target.set_response(response);
         target.wake_up_from(ProcessStatus::SERVICE);
-        self.yield_to_process(target);
+        return Some(target);
+    }
+}
+
+/**
+ * Priority inheritance: a servant runs at the highest priority among
+ * the clients it is serving, and those still waiting at the same ports.
+ * The boost drops once it has responded to all of them.
+ */
+unsafe fn refresh_boost(servant : usize) {
+    let servant = match PidType::new(servant).try_to_process() {
+        Some(servant)   => &mut *servant,
+        None            => return,
+    };
+    let mut boost = 0;
+    for (handle, port) in inflight::served_by(servant.get_pid().bits()) {
+        boost = max(boost, ServiceHandle::new(handle).get_priority());
+        if let Some(service) = registry::get_service(port) {
+            boost = max(boost, service.max_priority());
+        }
+    }
+    servant.set_boost(boost);
+}
+
+/**
+ * Cancel those requests whose handle matches %filter.
+ * Those in flight are withdrawn from the servants, which may be notified.
+ * Their lent pages stay mapped until the servants respond, so that
+ * no servant faults on a request cancelled under it.
+ */
+unsafe fn cancel_requests(filter : impl Fn(usize) -> bool + Copy) {
+    registry::for_each_service(|port, service| {
+        for handle in service.cancel(filter) { stats::on_cancel(port, handle); }
+    });
+
+    for (handle, serving) in inflight::cancel(|x, _| filter(x)) {
+        stats::on_cancel(serving.port, handle);
+        if registry::wants_cancel(serving.port) {
+            service_request_async(Argument::Register(handle, 0), IPC_CANCEL, serving.port);
+        }
+    }
+}
+
+/**
+ * Called on timer ticks. Wake up those blocked in request or receive
+ * whose deadline has passed. Requests of timed out clients are cancelled.
+ */
+pub unsafe fn service_check_timeout() {
+    for pid in timeout::get_sleepers() {
+        let process = match PidType::new(pid).try_to_process() {
+            Some(process)   => &mut *process,
+            None            => { timeout::leave(pid); continue; },
+        };
+        if !process.is_expired() { continue; }
+
+        if process.has_status(ProcessStatus::SERVICE) {
+            // A process blocks on one request at most, so the only sync one.
+            let pid = process.get_pid().bits();
+            cancel_requests(|x| {
+                let handle = ServiceHandle::new(x);
+                return handle.is_from(pid) && handle.get_ticket() == 0;
+            });
+            process.set_response(Argument::Register(SERVICE_TIMEOUT, 0));
+            process.wake_up_from(ProcessStatus::SERVICE);
+        } else if process.has_status(ProcessStatus::SERVING) {
+            let servant = process as *mut Process;
+            registry::for_each_service(|_, service| service.remove_servant(servant));
+            process.wake_up_from(ProcessStatus::SERVING);
+        }
+        // Otherwise, it has been woken up and will leave soon.
+    }
+}
+
+/** Tell the requester of %handle that the request fails with %error, e.g. SERVICE_DEAD. */
+unsafe fn fail_request(handle : ServiceHandle, error : usize) {
+    if handle.is_async() { return; }
+    let target = match handle.try_to_process() {
+        Some(target) => &mut *target,
+        None         => return,
+    };
+    let response = Argument::Register(error, 0);
+    match handle.get_ticket() {
+        0 => {
+            target.set_response(response);
+            target.wake_up_from(ProcessStatus::SERVICE);
+        },
+        ticket => {
+            target.push_completion(ticket, response);
+            if target.has_status(ProcessStatus::POLLING) {
+                target.wake_up_from(ProcessStatus::POLLING);
+            }
+        }
     }
 }
 
 pub unsafe fn service_request_async(args : Argument, kind : usize, port : usize) {
-    let service = &mut SERVICE[port];
-    service.push_back(Request::new(args, kind, ServiceHandle::new_async()));
-    service.try_wake_up_servant();
+    submit(port, Request::new(args, kind, ServiceHandle::new_async()));
+    get_service(port).try_wake_up_servant();
 }
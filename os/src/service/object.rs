@@ -0,0 +1,121 @@
+extern crate alloc;
+use alloc::{sync::Arc, vec::Vec};
+
+use sys::syscall::SERVICE_ERROR;
+
+use crate::{alloc::{SharedPages, PAGE_SIZE}, proc::{PidType, Process}};
+
+use super::inflight;
+
+/** Kernel-managed objects, which can be moved through IPC. */
+#[derive(Clone)]
+pub enum KernelObject {
+    Port(usize, usize), // Rights on a port
+    Reply(usize),       // Right to respond to an in-flight request
+    Shm(Arc<SharedPages>), // Shared memory, freed with the last reference
+}
+
+/** Objects held by a process, indexed by the local handle. */
+#[derive(Clone)]
+pub struct HandleTable(Vec<Option<KernelObject>>);
+
+impl HandleTable {
+    pub const fn new() -> Self {
+        return Self(Vec::new());
+    }
+
+    /** Insert an object, and return the local handle. */
+    pub fn insert(&mut self, object : KernelObject) -> usize {
+        match self.0.iter().position(|x| x.is_none()) {
+            Some(index) => {
+                self.0[index] = Some(object);
+                return index;
+            },
+            None => {
+                self.0.push(Some(object));
+                return self.0.len() - 1;
+            }
+        }
+    }
+
+    /** Return the object without taking it. */
+    pub fn get(&self, index : usize) -> Option<&KernelObject> {
+        return self.0.get(index)?.as_ref();
+    }
+
+    /** Take the object out of the table. */
+    pub fn take(&mut self, index : usize) -> Option<KernelObject> {
+        return self.0.get_mut(index)?.take();
+    }
+
+    /** Count of the objects held. */
+    pub fn count(&self) -> usize {
+        return self.0.iter().filter(|x| x.is_some()).count();
+    }
+
+    /** Drop the reply object of %handle, if any. */
+    pub fn remove_reply(&mut self, handle : usize) {
+        for slot in self.0.iter_mut() {
+            if let Some(KernelObject::Reply(x)) = slot {
+                if *x == handle { *slot = None; }
+            }
+        }
+    }
+
+    /** Strip the rights on %port, which is gone. The handles stay valid. */
+    pub fn revoke(&mut self, port : usize) {
+        for object in self.0.iter_mut().flatten() { object.revoke(port); }
+    }
+
+    /** Copy for a forked child. Replies belong to the parent only. */
+    pub fn fork(&self) -> Self {
+        return Self(self.0.iter().map(|x| match x {
+            Some(KernelObject::Reply(_)) => None,
+            _ => x.clone(),
+        }).collect());
+    }
+}
+
+impl KernelObject {
+    /** Strip the rights if it is on %port, which is gone. */
+    pub fn revoke(&mut self, port : usize) {
+        if let Self::Port(x, rights) = self {
+            if *x == port { *rights = 0; }
+        }
+    }
+
+    /** The value visible to user, e.g. the port, the reply handle or the size. */
+    pub fn get_value(&self) -> usize {
+        match self {
+            Self::Port(port, _)  => *port,
+            Self::Reply(handle)  => *handle,
+            Self::Shm(pages)     => pages.get_pages().len() * PAGE_SIZE,
+        }
+    }
+
+    /**
+     * Translate the object into the handle table of %target,
+     * taking effect in the kernel. Return the local handle, or
+     * SERVICE_ERROR if %target holds too many, with the object dropped
+     * (a reply then stays with the old servant).
+     */
+    pub unsafe fn install(self, target : &mut Process) -> usize {
+        if !target.can_hold_handle() { return SERVICE_ERROR; }
+        match &self {
+            Self::Port(port, rights) => {
+                target.get_caps().grant(*port, *rights);
+            },
+            Self::Reply(handle) => {
+                // The lent pages are for the old servant only.
+                let old = inflight::delegate(*handle, target.get_pid().bits());
+                if let Some(servant) = old.and_then(|x| PidType::new(x).try_to_process()) {
+                    (*servant).get_memory_area().unlend(*handle);
+                }
+            },
+            Self::Shm(_) => {
+                // Nothing to do until it is mapped.
+            },
+        }
+        return target.get_handles().insert(self);
+    }
+}
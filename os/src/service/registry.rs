@@ -0,0 +1,166 @@
+extern crate alloc;
+use alloc::{boxed::Box, collections::{BTreeMap, VecDeque}, vec::Vec};
+use sys::syscall::{PM_NAME, PM_PORT, SERVICE_PORT_LIMIT};
+
+use crate::proc::for_each_process;
+use super::{request::Request, service::Service};
+
+struct Entry {
+    name    : Box<[u8]>,    // Name of the service
+    owner   : usize,        // Pid of the owner (0 if unclaimed)
+    notify  : bool,         // Notify the servant of cancellation?
+    service : Service,      // The service itself
+}
+
+/**
+ * Services indexed by port. Each entry is boxed, so a service
+ * never moves even if the table grows (someone may sleep on it).
+ */
+static mut REGISTRY : Vec<Option<Box<Entry>>> = Vec::new();
+
+/** Ports indexed by the name, for those named. */
+static mut NAMES : BTreeMap<Box<[u8]>, usize> = BTreeMap::new();
+
+/** The table, with the process manager registered at PM_PORT. */
+unsafe fn get_table() -> &'static mut Vec<Option<Box<Entry>>> {
+    if REGISTRY.is_empty() {
+        let name : Box<[u8]> = Box::from(PM_NAME.as_bytes());
+        NAMES.insert(name.clone(), PM_PORT);
+        REGISTRY.push(Some(Box::new(Entry { name, owner : 0, notify : false, service : Service::new() })));
+        assert!(REGISTRY.len() == PM_PORT + 1);
+    }
+    return &mut REGISTRY;
+}
+
+/** Revoke all the rights on %port, so stale ones grant nothing. */
+unsafe fn revoke_rights(port : usize) {
+    for_each_process(|process| {
+        process.get_caps().revoke(port);
+        process.get_handles().revoke(port);
+    });
+    for_each_service(|_, service| service.revoke(port));
+}
+
+/** Free the slot of %port, so that the port can be reused. */
+unsafe fn free_port(port : usize) {
+    revoke_rights(port);
+    let entry = get_table()[port].take().expect("Freeing an empty port");
+    NAMES.remove(&entry.name);
+}
+
+/** Return the service at %port, or None if not registered. */
+pub(super) unsafe fn get_service(port : usize) -> Option<&'static mut Service> {
+    match get_table().get_mut(port) {
+        Some(Some(entry)) => Some(&mut entry.service),
+        _ => None,
+    }
+}
+
+/**
+ * Whether %pid may receive on %port.
+ * An unclaimed port (e.g. PM_PORT) is claimed by its first receiver,
+ * so that the kernel knows who to blame if the service dies.
+ */
+pub(super) unsafe fn can_serve(port : usize, pid : usize) -> bool {
+    match get_table().get_mut(port) {
+        Some(Some(entry)) => {
+            if entry.owner == 0 { entry.owner = pid; }
+            return true;
+        },
+        _ => return false,
+    }
+}
+
+/** Return all the ports owned by %pid. */
+pub(super) unsafe fn owned_by(pid : usize) -> Vec<usize> {
+    let table = get_table();
+    return (0..table.len()).filter(|port| match &table[*port] {
+        Some(entry) => entry.owner == pid,
+        None        => false,
+    }).collect();
+}
+
+/**
+ * Release the %port whose owner has died, so it can be claimed again.
+ * Return the requests queued, which the caller should fail.
+ * PM_PORT always stays there, waiting for a new process manager.
+ * If some servants are still waiting on it, the entry is kept without
+ * a name, since they are sleeping on the service. The rights on it are
+ * revoked at once anyway, so no more requests can be sent.
+ */
+pub(super) unsafe fn release(port : usize) -> VecDeque<Request> {
+    let slot  = &mut get_table()[port];
+    let entry = slot.as_mut().expect("Releasing an empty port");
+    let queue = entry.service.take_all();
+    entry.owner = 0;
+    if port == PM_PORT { return queue; }
+    if entry.service.is_idle() {
+        free_port(port);
+    } else {
+        NAMES.remove(&entry.name);
+        entry.name   = Box::from([].as_slice());
+        entry.notify = false;
+        revoke_rights(port);
+    }
+    return queue;
+}
+
+/** Return the port of service named %name. */
+pub(super) unsafe fn lookup(name : &[u8]) -> Option<usize> {
+    get_table();
+    return NAMES.get(name).copied();
+}
+
+/** Whether the servants at %port want to be notified of cancellation. */
+pub(super) unsafe fn wants_cancel(port : usize) -> bool {
+    match get_table().get(port) {
+        Some(Some(entry)) => entry.notify,
+        _ => false,
+    }
+}
+
+/** Apply %func to all the services, with their ports. */
+pub(super) unsafe fn for_each_service(mut func : impl FnMut(usize, &mut Service)) {
+    for (port, slot) in get_table().iter_mut().enumerate() {
+        if let Some(entry) = slot { func(port, &mut entry.service); }
+    }
+}
+
+/**
+ * Claim a port with %name for process %owner, reusing a free one if any.
+ * Return None if the name is already taken, or %owner has too many ports.
+ */
+pub(super) unsafe fn register(name : &[u8], owner : usize, notify : bool) -> Option<usize> {
+    if name.is_empty() || lookup(name).is_some() { return None; }
+    if owned_by(owner).len() >= SERVICE_PORT_LIMIT { return None; }
+    let entry = Box::new(Entry { name : Box::from(name), owner, notify, service : Service::new() });
+    let table = get_table();
+    let port = match table.iter().position(|x| x.is_none()) {
+        Some(port) => port,
+        None => {
+            table.push(None);
+            table.len() - 1
+        },
+    };
+    table[port] = Some(entry);
+    NAMES.insert(Box::from(name), port);
+    return Some(port);
+}
+
+/**
+ * Release the %port owned by %owner.
+ * Fail if not the owner, or the service is still in use.
+ */
+pub(super) unsafe fn unregister(port : usize, owner : usize) -> bool {
+    let slot = match get_table().get_mut(port) {
+        Some(slot) => slot,
+        None       => return false,
+    };
+    match slot {
+        Some(entry) if entry.owner == owner && entry.service.is_idle() => {
+            free_port(port);
+            return true;
+        },
+        _ => return false,
+    }
+}
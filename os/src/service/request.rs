@@ -1,25 +1,55 @@
-use sys::syscall::{ARGS_BUFFERED, ARGS_REGISTER};
+use sys::syscall::{ARGS_BUFFERED, ARGS_CAPABILITY, ARGS_HANDLE, ARGS_MAPPING, ARGS_REGISTER, ARGS_WIDE};
 
-use crate::{alloc::PTEFlag, proc::Process, utility::SliceIter};
+use crate::{alloc::PTEFlag, driver::timer::get_time, proc::Process, utility::SliceIter};
 
-use super::{argv::Argument, handle::ServiceHandle};
+use super::{argv::{set_wide, Argument}, handle::ServiceHandle};
+
+/** Outcome of forwarding a request to a servant. */
+pub enum Forward {
+    Done,   // Delivered to the servant
+    Retry,  // The servant should enlarge its buffer and receive again
+    Failed, // The buffer of the caller is gone, so the request fails
+}
 
 pub struct Request {
     args    : Argument,         // Arguments
     kind    : usize,            // What kind of service?
     handle  : ServiceHandle,    // The callback handle
+    since   : usize,            // When it is requested
 }
 
 impl Request {
     pub unsafe fn new(args : Argument, kind : usize, handle  : ServiceHandle) -> Self {
-        return Self { kind, args, handle };
+        return Self { kind, args, handle, since : get_time() };
     }
 
-    /**
-     * Try to forward a request to %target process.
-     * Return whether the request can be forwarded.
-    */
-    pub unsafe fn try_forward(&mut self, target : &mut Process) -> bool {
+    pub fn get_since(&self) -> usize {
+        return self.since;
+    }
+
+    pub fn get_length(&self) -> usize {
+        return self.args.get_length();
+    }
+
+    /** Strip the rights on %port carried, which is gone. */
+    pub fn revoke(&mut self, port : usize) {
+        match &mut self.args {
+            Argument::Capability(x, rights) if *x == port => *rights = 0,
+            Argument::Handle(object) => object.revoke(port),
+            _ => {},
+        }
+    }
+
+    pub fn get_handle(&self) -> usize {
+        return self.handle.bits();
+    }
+
+    pub unsafe fn get_priority(&self) -> usize {
+        return self.handle.get_priority();
+    }
+
+    /** Try to forward a request to %target process. */
+    pub unsafe fn try_forward(&mut self, target : &mut Process) -> Forward {
         let trap_frame = target.get_trap_frame();
         match &mut self.args {
             Argument::Register(a0, a1) => {
@@ -29,12 +59,18 @@ impl Request {
                 trap_frame.a4 = self.kind;
                 trap_frame.a5 = self.handle.bits();
             },
+            Argument::Wide(words) => {
+                set_wide(trap_frame, words);
+                trap_frame.a2 = ARGS_WIDE;
+                trap_frame.a4 = self.kind;
+                trap_frame.a5 = self.handle.bits();
+            },
             Argument::Buffered(buffer) => {
-                // Not enough space to write, so return false.
+                // Not enough space to write, so retry.
                 if trap_frame.a2 != ARGS_BUFFERED || trap_frame.a1 < buffer.len() {
                     trap_frame.a4 = self.kind;
                     trap_frame.a5 = buffer.len().wrapping_neg();
-                    return false;
+                    return Forward::Retry;
                 }
 
                 trap_frame.a1 = buffer.len();
@@ -48,13 +84,86 @@ impl Request {
                 target.address_check([buf, len], PTEFlag::WO);
                 target.get_satp().core_to_user(buf, len, SliceIter::new(buffer));
             },
-            Argument::Upointer(_, _) => {
-                // This is a zero-copy optimization.
-                // But not implemented yet.
-                let _caller = self.handle.clone().to_process();
-                todo!("Implement zero-copy optimization");
+            Argument::Upointer(ptr, size) => {
+                let (ptr, size) = (*ptr, *size);
+                return self.forward_upointer(target, ptr, size);
+            },
+            Argument::Mapping(ptr, size) => {
+                let (ptr, size) = (*ptr as usize, *size);
+                let caller = &mut *self.handle.clone().to_process();
+                let memory = target.get_memory_area();
+                match memory.lend_from(self.handle.bits(), caller.get_satp(), ptr, size) {
+                    Some(address) => {
+                        let trap_frame = target.get_trap_frame();
+                        trap_frame.a0 = address;
+                        trap_frame.a1 = size;
+                        trap_frame.a2 = ARGS_MAPPING;
+                        trap_frame.a4 = self.kind;
+                        trap_frame.a5 = self.handle.bits();
+                    },
+                    None => {
+                        // Lending window is full, fall back to copying.
+                        return self.forward_upointer(target, ptr as *mut u8, size);
+                    }
+                }
+            },
+            Argument::Capability(port, rights) => {
+                target.get_caps().grant(*port, *rights);
+                let trap_frame = target.get_trap_frame();
+                trap_frame.a0 = *port;
+                trap_frame.a1 = *rights;
+                trap_frame.a2 = ARGS_CAPABILITY;
+                trap_frame.a4 = self.kind;
+                trap_frame.a5 = self.handle.bits();
+            },
+            Argument::Handle(object) => {
+                let value = object.get_value();
+                let index = object.clone().install(target);
+                let trap_frame = target.get_trap_frame();
+                trap_frame.a0 = index;
+                trap_frame.a1 = value;
+                trap_frame.a2 = ARGS_HANDLE;
+                trap_frame.a4 = self.kind;
+                trap_frame.a5 = self.handle.bits();
             }
         }
-        return true;
+        return Forward::Done;
+    }
+
+    /**
+     * Copy the caller's buffer directly into the servant's buffer.
+     * Only blocking requests carry user pointers (async ones are copied
+     * on submit), and the caller is blocked until this is done.
+     * Fail if the buffer of the caller is gone (e.g. unmapped).
+     */
+    unsafe fn forward_upointer(&self, target : &mut Process, ptr : *mut u8, size : usize) -> Forward {
+        let trap_frame = target.get_trap_frame();
+
+        // Not enough space to write, so retry.
+        if trap_frame.a2 != ARGS_BUFFERED || trap_frame.a1 < size {
+            trap_frame.a4 = self.kind;
+            trap_frame.a5 = size.wrapping_neg();
+            return Forward::Retry;
+        }
+
+        let buf = trap_frame.a0;
+        let len = size;
+
+        // Zero-copy: copy directly from the caller's address space.
+        // The frame is left as is on failure, to receive the next one.
+        let caller = &mut *self.handle.clone().to_process();
+        target.address_check([buf, len], PTEFlag::WO);
+        if !target.get_satp().user_to_user(buf, caller.get_satp(), ptr as usize, len) {
+            warning!("Invalid buffer of request {:#x}", self.handle.bits());
+            return Forward::Failed;
+        }
+
+        // To the servant, it is just an ordinary buffer.
+        let trap_frame = target.get_trap_frame();
+        trap_frame.a1 = size;
+        trap_frame.a2 = ARGS_BUFFERED;
+        trap_frame.a4 = self.kind;
+        trap_frame.a5 = self.handle.bits();
+        return Forward::Done;
     }
 }
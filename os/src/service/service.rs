@@ -1,54 +1,96 @@
 use crate::proc::{Process, ProcessStatus};
 extern crate alloc;
-use alloc::collections::VecDeque;
+use alloc::{collections::VecDeque, vec::Vec};
 
 use super::request::Request;
 
 pub struct Service {
-    servant : *mut Process,     // Who is accepting?
-    waiting : VecDeque<Request> // Pending requests
+    servant : VecDeque<*mut Process>,   // Who are accepting?
+    waiting : VecDeque<Request>         // Pending requests
 }
 
 impl Service {
     pub const fn new() -> Self {
         Service {
-            servant : core::ptr::null_mut(),
+            servant : VecDeque::new(),
             waiting : VecDeque::new(),
         }
     }
 
-    unsafe fn set_servant(&mut self, process: *mut Process) {
-        assert!(self.servant.is_null(), "Service already accepted");
-        self.servant = process;
+    pub unsafe fn add_servant(&mut self, process: *mut Process) {
+        assert!(!self.servant.contains(&process), "Service already accepted");
+        self.servant.push_back(process);
     }
 
-    unsafe fn reset_servant(&mut self, process: *mut Process) {
-        assert!(self.servant == process, "Invalid servant");
-        self.servant = core::ptr::null_mut();
+    pub unsafe fn remove_servant(&mut self, process: *mut Process) {
+        self.servant.retain(|servant| *servant != process);
     }
 
-    pub unsafe fn try_wake_up_servant(&self) -> Option<* mut Process> {
-        if self.servant.is_null() { return None; }
-        (*self.servant).wake_up_from(ProcessStatus::SERVING);
-        return Some(self.servant);
+    /**
+     * Wake up the first idle servant, if any.
+     * A servant waiting on many ports may have been woken up by
+     * another port, so those not sleeping any more are skipped.
+     */
+    pub unsafe fn try_wake_up_servant(&mut self) -> Option<* mut Process> {
+        while let Some(servant) = self.servant.pop_front() {
+            if (*servant).has_status(ProcessStatus::SERVING) {
+                (*servant).wake_up_from(ProcessStatus::SERVING);
+                return Some(servant);
+            }
+        }
+        return None;
     }
 
-    pub unsafe fn wait_for_request(&mut self, process : &mut Process) -> &mut Request {
-        core::hint::black_box(&self.servant);
-        self.set_servant(process);
+    pub fn has_request(&self) -> bool {
+        // Something may be changed to the waiting
+        // but the compiler might optimize it out
+        // So we might tell the compiler that waiting has changed
+        core::hint::black_box(&self.waiting);
+        return !self.waiting.is_empty();
+    }
 
-        while self.waiting.is_empty() {
-            process.sleep_as(ProcessStatus::SERVING);
-            process.yield_to_scheduler();
-            // Something may be changed to the waiting
-            // but the compiler might optimize it out
-            // So we might tell the compiler that waiting has changed
-            core::hint::black_box(&self.waiting);
-        }
+    /** The highest priority of those blocked in the queue. */
+    pub unsafe fn max_priority(&self) -> usize {
+        return self.waiting.iter().map(|x| x.get_priority()).max().unwrap_or(0);
+    }
 
-        self.reset_servant(process);
+    pub fn front_mut(&mut self) -> Option<&mut Request> {
+        return self.waiting.front_mut();
+    }
+
+    /** Whether no one is serving or waiting for the service. */
+    pub fn is_idle(&self) -> bool {
+        return self.servant.is_empty() && self.waiting.is_empty();
+    }
+
+    /**
+     * Drop those pending requests whose handle matches %filter.
+     * Return the handles dropped.
+     */
+    pub unsafe fn cancel(&mut self, filter : impl Fn(usize) -> bool) -> Vec<usize> {
+        let mut dropped = Vec::new();
+        self.waiting.retain(|request| {
+            let handle = request.get_handle();
+            if !filter(handle) { return true; }
+            dropped.push(handle);
+            return false;
+        });
+        return dropped;
+    }
+
+    /** Strip the rights on %port carried by the pending requests. */
+    pub fn revoke(&mut self, port : usize) {
+        for request in self.waiting.iter_mut() { request.revoke(port); }
+    }
+
+    /** Number of pending requests. */
+    pub fn depth(&self) -> usize {
+        return self.waiting.len();
+    }
 
-        return self.waiting.front_mut().expect("WTF no request!");
+    /** Take all the pending requests out. */
+    pub unsafe fn take_all(&mut self) -> VecDeque<Request> {
+        return core::mem::take(&mut self.waiting);
     }
 
     pub unsafe fn pop_front(&mut self) {
@@ -0,0 +1,73 @@
+extern crate alloc;
+use alloc::{collections::{BTreeMap, VecDeque}, vec::Vec};
+use sys::syscall::{IpcEvent, IpcStat, IPC_EVENT_CANCEL, IPC_EVENT_RECEIVE, IPC_EVENT_REQUEST, IPC_EVENT_RESPOND, IPC_TRACE_LIMIT};
+
+use crate::driver::timer::get_time;
+
+/**
+ * Counters indexed by port. They outlive the port for post-mortem,
+ * until the port is reused by a new service.
+ */
+static mut STATS : BTreeMap<usize, IpcStat> = BTreeMap::new();
+
+/** Recent events, the oldest dropped when full. */
+static mut TRACE : VecDeque<IpcEvent> = VecDeque::new();
+static mut TRACING : bool = false;
+
+unsafe fn get_stat(port : usize) -> &'static mut IpcStat {
+    return STATS.entry(port).or_default();
+}
+
+unsafe fn trace(event : usize, port : usize, handle : usize) {
+    if !TRACING { return; }
+    if TRACE.len() == IPC_TRACE_LIMIT { TRACE.pop_front(); }
+    TRACE.push_back(IpcEvent { time : get_time(), event, port, handle });
+}
+
+/** A request of %bytes is queued at %port, which now holds %depth. */
+pub(super) unsafe fn on_request(port : usize, handle : usize, bytes : usize, depth : usize) {
+    let stat = get_stat(port);
+    stat.requests  += 1;
+    stat.bytes     += bytes;
+    stat.max_depth  = stat.max_depth.max(depth);
+    trace(IPC_EVENT_REQUEST, port, handle);
+}
+
+/** Drop the counters of %port, which now serves a new service. */
+pub(super) unsafe fn reset(port : usize) {
+    STATS.remove(&port);
+}
+
+pub(super) unsafe fn on_receive(port : usize, handle : usize) {
+    trace(IPC_EVENT_RECEIVE, port, handle);
+}
+
+/** The request queued at %since is responded with %bytes. */
+pub(super) unsafe fn on_respond(port : usize, handle : usize, bytes : usize, since : usize) {
+    let stat = get_stat(port);
+    stat.responses += 1;
+    stat.bytes     += bytes;
+    stat.latency   += get_time() - since;
+    trace(IPC_EVENT_RESPOND, port, handle);
+}
+
+pub(super) unsafe fn on_cancel(port : usize, handle : usize) {
+    get_stat(port).cancels += 1;
+    trace(IPC_EVENT_CANCEL, port, handle);
+}
+
+/** Return the first port no less than %port with counters, and them. */
+pub(super) unsafe fn next_stat(port : usize) -> Option<(usize, IpcStat)> {
+    return STATS.range(port..).next().map(|(x, y)| (*x, *y));
+}
+
+/** Turn the trace on or off. Events are kept until taken. */
+pub(super) unsafe fn set_tracing(on : bool) {
+    TRACING = on;
+}
+
+/** Take at most %count oldest events out of the trace. */
+pub(super) unsafe fn take_trace(count : usize) -> Vec<IpcEvent> {
+    let count = count.min(TRACE.len());
+    return TRACE.drain(..count).collect();
+}
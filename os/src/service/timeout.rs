@@ -0,0 +1,20 @@
+extern crate alloc;
+use alloc::{collections::BTreeSet, vec::Vec};
+
+/** Pids of those processes blocked in IPC with a deadline. */
+static mut SLEEPERS : BTreeSet<usize> = BTreeSet::new();
+
+/** %pid is going to block with a deadline. */
+pub(super) unsafe fn enter(pid : usize) {
+    SLEEPERS.insert(pid);
+}
+
+/** %pid has returned from the blocking call. */
+pub(super) unsafe fn leave(pid : usize) {
+    SLEEPERS.remove(&pid);
+}
+
+/** Return all the sleepers, since the table may change when handling. */
+pub(super) unsafe fn get_sleepers() -> Vec<usize> {
+    return SLEEPERS.iter().copied().collect();
+}
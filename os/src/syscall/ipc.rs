@@ -1,26 +1,54 @@
-use crate::{cpu::CPU, service::Argument};
+extern crate alloc;
+use alloc::{vec::Vec, boxed::Box};
+use core::mem::size_of;
+use sys::syscall::{ARGS_BUFFERED, ARGS_REGISTER, CAP_RECEIVE, CAP_SEND, PM_PORT, PM_USER, SERVICE_DENIED, SERVICE_ERROR, SERVICE_NAME_LIMIT, SERVICE_NOTIFY, SERVICE_SELECT_LIMIT, SERVICE_TIMEOUT};
+
+use crate::{alloc::PTEFlag, cpu::CPU, driver::timer::{get_time, Time}, proc::Process, service::{service_exists, service_lookup, service_stat, service_trace, set_wide, Argument, KernelObject}, utility::SliceIter};
 
 impl CPU {
     /**
-     * A blocking request sent by a trusted process to the kernel.
+     * A blocking request sent by a process with the right to send.
      * A request may be redirected to another process or kernel.
      * This process will continue to run after the request is processed.
+     * A non-register response is copied into the buffer in a3 and a5.
+     * An optional timeout in milliseconds is passed in t0.
+     * Return SERVICE_ERROR if the port is not registered,
+     * SERVICE_DEAD if the servant dies before responding,
+     * SERVICE_TIMEOUT if the timeout expires,
+     * or SERVICE_DENIED if the right to send is not held.
      */
     pub unsafe fn sys_request(&mut self) {
         let process     = &mut *self.get_process();
         let trap_frame  = process.get_trap_frame();
         let port        = trap_frame.a6;
+        if !service_exists(port) {
+            trap_frame.a0 = SERVICE_ERROR;
+            return;
+        }
         let kind        = trap_frame.a4;
-        let args        = Argument::new([trap_frame.a0, trap_frame.a1, trap_frame.a2], process);
+        let buffer      = [trap_frame.a3, trap_frame.a5];
+        let arg_array   = [trap_frame.a0, trap_frame.a1, trap_frame.a2];
+        if !can_send(process, port, kind) {
+            process.get_trap_frame().a0 = SERVICE_DENIED;
+            return;
+        }
+        let args        = Argument::new(arg_array, process);
+        process.address_check(buffer, PTEFlag::WO);
+        process.set_receive_buffer(buffer);
+        set_timeout(process);
         process.service_request(args, kind, port);
+        process.set_receive_buffer([0, 0]);
         match process.get_response() {
             Some(response) => {
                 match response {
                     Argument::Register(x, _) => {
                         process.get_trap_frame().a0 = x;
                     }
+                    Argument::Wide(words) => {
+                        set_wide(process.get_trap_frame(), &words);
+                    }
                     _ => {
-                        todo!();
+                        unreachable!("Response should have been copied");
                     }
                 }
             },
@@ -31,20 +59,70 @@ impl CPU {
     }
 
     /**
-     * A blocking accept sent by a trusted process to the kernel.
-     * Only one process can accept one certain request.
+     * A blocking accept sent by a process with the right to receive.
+     * Only one process can accept one certain request,
+     * while many processes may wait on the same port.
+     * An optional timeout in milliseconds is passed in t0,
+     * and SERVICE_TIMEOUT is returned in a2 if it expires.
      */
     pub unsafe fn sys_receive(&mut self) {
         let process     = &mut *self.get_process();
-        let trap_frame  = process.get_trap_frame();
-        let port        = trap_frame.a6;
-        process.service_receive(port);
+        let port        = process.get_trap_frame().a6;
+        let error       = if !process.get_caps().has(port, CAP_RECEIVE) {
+            SERVICE_DENIED
+        } else if !process.service_can_receive(port) {
+            SERVICE_ERROR
+        } else {
+            0
+        };
+        if error != 0 {
+            let trap_frame = process.get_trap_frame();
+            trap_frame.a2 = error;
+            trap_frame.a5 = 0;
+            return;
+        }
+        set_timeout(process);
+        if !process.service_receive(port) {
+            let trap_frame = process.get_trap_frame();
+            trap_frame.a2 = SERVICE_TIMEOUT;
+            trap_frame.a5 = 0;
+        }
+    }
+
+    /**
+     * A blocking accept on many ports, whose array is in a3 and a5.
+     * Return like sys_receive, with the port of the request in a6.
+     * The former ports are preferred if many have requests.
+     */
+    pub unsafe fn sys_receive_any(&mut self) {
+        let process     = &mut *self.get_process();
+        let ports       = match read_ports(process) {
+            Some(ports) => ports,
+            None        => {
+                let trap_frame = process.get_trap_frame();
+                trap_frame.a2 = SERVICE_ERROR;
+                trap_frame.a5 = 0;
+                return;
+            }
+        };
+        set_timeout(process);
+        match process.service_receive_any(&ports) {
+            Some(port)  => {
+                process.get_trap_frame().a6 = port;
+            },
+            None        => {
+                let trap_frame = process.get_trap_frame();
+                trap_frame.a2 = SERVICE_TIMEOUT;
+                trap_frame.a5 = 0;
+            }
+        }
     }
 
     /**
      * A response sent by a trusted process to the kernel.
      * This will send the response to the handle, which is the caller
      * of the request. After the reponse, the caller will continue to run.
+     * Return SERVICE_ERROR if the request has been cancelled.
      */
     pub unsafe fn sys_respond(&mut self) {
         let process     = &mut *self.get_process();
@@ -52,6 +130,312 @@ impl CPU {
         let arg_array   = [trap_frame.a0, trap_frame.a1, trap_frame.a2];
         let handle      = trap_frame.a5;
         let argument    = Argument::new(arg_array, process);
-        process.service_respond(argument, handle);
+        let result      = process.service_respond(argument, handle);
+        process.get_trap_frame().a0 = if result { 0 } else { SERVICE_ERROR };
+    }
+
+    /**
+     * Respond to the handle in a5 with a0, a1 and a2 (like sys_respond),
+     * then receive on the port in a6 (like sys_receive), with the
+     * buffer in a3 and a4. The response result is returned in a3.
+     * This saves a kernel entry and a switch to the scheduler.
+     */
+    pub unsafe fn sys_reply_and_receive(&mut self) {
+        let process     = &mut *self.get_process();
+        let trap_frame  = process.get_trap_frame();
+        let arg_array   = [trap_frame.a0, trap_frame.a1, trap_frame.a2];
+        let buffer      = [trap_frame.a3, trap_frame.a4];
+        let handle      = trap_frame.a5;
+        let port        = trap_frame.a6;
+        let error       = if !process.get_caps().has(port, CAP_RECEIVE) {
+            SERVICE_DENIED
+        } else if !process.service_can_receive(port) {
+            SERVICE_ERROR
+        } else {
+            0
+        };
+        if error != 0 {
+            let trap_frame = process.get_trap_frame();
+            trap_frame.a2 = error;
+            trap_frame.a3 = SERVICE_ERROR;
+            trap_frame.a5 = 0;
+            return;
+        }
+
+        let argument    = Argument::new(arg_array, process);
+
+        // Then it looks like an ordinary receive.
+        let trap_frame  = process.get_trap_frame();
+        trap_frame.a0   = buffer[0];
+        trap_frame.a1   = buffer[1];
+        trap_frame.a2   = if buffer[0] != 0 { ARGS_BUFFERED } else { ARGS_REGISTER };
+
+        set_timeout(process);
+        let (responded, received) = process.service_reply_and_receive(argument, handle, port);
+        let trap_frame  = process.get_trap_frame();
+        trap_frame.a3   = if responded { 0 } else { SERVICE_ERROR };
+        if !received {
+            trap_frame.a2 = SERVICE_TIMEOUT;
+            trap_frame.a5 = 0;
+        }
+    }
+
+    /**
+     * Read the counters of the first port no less than a0 into the
+     * IpcStat at a1. Return that port in a0, or SERVICE_ERROR if none.
+     * Only the process manager (who receives at PM_PORT) may read them.
+     */
+    pub unsafe fn sys_ipc_stat(&mut self) {
+        let process     = &mut *self.get_process();
+        let trap_frame  = process.get_trap_frame();
+        let (port, buf) = (trap_frame.a0, trap_frame.a1);
+        if !process.get_caps().has(PM_PORT, CAP_RECEIVE) {
+            process.get_trap_frame().a0 = SERVICE_DENIED;
+            return;
+        }
+        process.get_trap_frame().a0 = match service_stat(port) {
+            Some((port, stat)) => {
+                copy_out(process, buf, &[stat]);
+                port
+            },
+            None => SERVICE_ERROR,
+        };
+    }
+
+    /**
+     * Switch the IPC trace by a2 (IPC_TRACE_*), and take the oldest
+     * events into the IpcEvent array at a0, whose length is a1.
+     * Return the number of events taken. Only for the process manager.
+     */
+    pub unsafe fn sys_ipc_trace(&mut self) {
+        let process     = &mut *self.get_process();
+        let trap_frame  = process.get_trap_frame();
+        let (buf, count, flag) = (trap_frame.a0, trap_frame.a1, trap_frame.a2);
+        if !process.get_caps().has(PM_PORT, CAP_RECEIVE) {
+            process.get_trap_frame().a0 = SERVICE_DENIED;
+            return;
+        }
+        let events = service_trace(flag, count);
+        copy_out(process, buf, &events);
+        process.get_trap_frame().a0 = events.len();
+    }
+
+    /**
+     * A non-blocking request sent by a trusted process to the kernel.
+     * Return a ticket at once. The response will be put in the
+     * completion queue of this process, tagged with the ticket.
+     * The caller keeps running, so its buffer may be gone before any
+     * servant receives: user pointers and lent pages are copied now.
+     * Fail if SERVICE_ASYNC_LIMIT responses are not taken yet.
+     */
+    pub unsafe fn sys_request_async(&mut self) {
+        let process     = &mut *self.get_process();
+        let trap_frame  = process.get_trap_frame();
+        let port        = trap_frame.a6;
+        if !service_exists(port) {
+            trap_frame.a0 = SERVICE_ERROR;
+            return;
+        }
+        let kind        = trap_frame.a4;
+        let arg_array   = [trap_frame.a0, trap_frame.a1, trap_frame.a2];
+        if !can_send(process, port, kind) {
+            process.get_trap_frame().a0 = SERVICE_DENIED;
+            return;
+        }
+        if !process.can_request_async() {
+            process.get_trap_frame().a0 = SERVICE_ERROR;
+            return;
+        }
+        let args        = Argument::new(arg_array, process).into_kernel(process.get_satp());
+        let ticket      = process.service_request_nonblock(args, kind, port);
+        process.get_trap_frame().a0 = ticket;
+    }
+
+    /**
+     * Poll (or wait if a0 is non-zero) the completion queue.
+     * Return the ticket in a0 (0 if nothing), and the response in a1
+     * (only the first word for a wide response).
+     * Buffered response is copied into the buffer in a2 and a3.
+     */
+    pub unsafe fn sys_complete(&mut self) {
+        let process     = &mut *self.get_process();
+        let trap_frame  = process.get_trap_frame();
+        let block       = trap_frame.a0 != 0;
+        let buffer      = [trap_frame.a2, trap_frame.a3];
+        process.address_check(buffer, PTEFlag::WO);
+        match process.service_complete(block) {
+            Some((ticket, response)) => {
+                let root = process.get_satp();
+                let response = response.into_response(root, buffer, root);
+                let trap_frame = process.get_trap_frame();
+                trap_frame.a0 = ticket;
+                trap_frame.a1 = response.get_register().unwrap().0;
+            },
+            None => {
+                process.get_trap_frame().a0 = 0;
+            }
+        }
+    }
+
+    /**
+     * Claim a port by the name in a0 and a1, with flags in a2.
+     * Return the port, or SERVICE_ERROR if the name is taken,
+     * or too many ports are owned (SERVICE_PORT_LIMIT).
+     */
+    pub unsafe fn sys_register(&mut self) {
+        let process = &mut *self.get_process();
+        let notify  = process.get_trap_frame().a2 & SERVICE_NOTIFY != 0;
+        let result  = match read_service_name(process) {
+            Some(name)  => process.service_register(&name, notify),
+            None        => None,
+        };
+        process.get_trap_frame().a0 = result.unwrap_or(SERVICE_ERROR);
+    }
+
+    /**
+     * Resolve the name in a0 and a1 to a port.
+     * Return SERVICE_ERROR if no such service.
+     */
+    pub unsafe fn sys_lookup(&mut self) {
+        let process = &mut *self.get_process();
+        let result  = match read_service_name(process) {
+            Some(name)  => service_lookup(&name),
+            None        => None,
+        };
+        process.get_trap_frame().a0 = result.unwrap_or(SERVICE_ERROR);
     }
+
+    /**
+     * Keep only the rights in a1 on the port in a0, which is
+     * usually done before exec to sandbox the new program.
+     */
+    pub unsafe fn sys_restrict(&mut self) {
+        let process     = &mut *self.get_process();
+        let trap_frame  = process.get_trap_frame();
+        let port        = trap_frame.a0;
+        let rights      = trap_frame.a1;
+        process.get_caps().restrict(port, rights);
+        process.get_trap_frame().a0 = 0;
+    }
+
+    /**
+     * Make a local handle of the rights in a1 on the port in a0,
+     * which can be moved to others through IPC.
+     * Return the handle, SERVICE_DENIED if the rights are not held,
+     * or SERVICE_ERROR if too many handles are held.
+     */
+    pub unsafe fn sys_handle_port(&mut self) {
+        let process     = &mut *self.get_process();
+        let trap_frame  = process.get_trap_frame();
+        let port        = trap_frame.a0;
+        let rights      = trap_frame.a1;
+        let result      = if !process.get_caps().has(port, rights) {
+            SERVICE_DENIED
+        } else if !process.can_hold_handle() {
+            SERVICE_ERROR
+        } else {
+            process.get_handles().insert(KernelObject::Port(port, rights))
+        };
+        process.get_trap_frame().a0 = result;
+    }
+
+    /**
+     * Make a local handle of the right to respond to the request
+     * of the handle in a0, so that it can be delegated to another
+     * servant. Return SERVICE_ERROR if the request is not ours,
+     * or too many handles are held.
+     */
+    pub unsafe fn sys_handle_reply(&mut self) {
+        let process     = &mut *self.get_process();
+        let handle      = process.get_trap_frame().a0;
+        let result      = match process.can_hold_handle() {
+            true    => process.service_handle_reply(handle),
+            false   => None,
+        };
+        process.get_trap_frame().a0 = result.unwrap_or(SERVICE_ERROR);
+    }
+
+    /** Drop the local handle in a0. */
+    pub unsafe fn sys_handle_close(&mut self) {
+        let process     = &mut *self.get_process();
+        let index       = process.get_trap_frame().a0;
+        let result      = process.get_handles().take(index);
+        process.get_trap_frame().a0 = if result.is_some() { 0 } else { SERVICE_ERROR };
+    }
+
+    /**
+     * Release the port in a0, which must be owned by this process
+     * and not in use. Return 0 on success, or SERVICE_ERROR.
+     */
+    pub unsafe fn sys_unregister(&mut self) {
+        let process = &mut *self.get_process();
+        let port    = process.get_trap_frame().a0;
+        let result  = process.service_unregister(port);
+        process.get_trap_frame().a0 = if result { 0 } else { SERVICE_ERROR };
+    }
+}
+
+/**
+ * Whether %process may request %kind at %port.
+ * Those kinds of PM below PM_USER can only be sent by kernel.
+ */
+unsafe fn can_send(process : &mut Process, port : usize, kind : usize) -> bool {
+    if port == PM_PORT && kind < PM_USER { return false; }
+    return process.get_caps().has(port, CAP_SEND);
+}
+
+/** Set the deadline by the timeout (in milliseconds) in t0. */
+unsafe fn set_timeout(process : &mut Process) {
+    let timeout = process.get_trap_frame().t0;
+    if timeout != 0 {
+        process.set_deadline(get_time() + usize::from(Time::millisecond(timeout)));
+    }
+}
+
+/**
+ * Read the array of ports in a3 and a5 into kernel.
+ * Return None if any port is invalid or duplicated.
+ */
+unsafe fn read_ports(process : &mut Process) -> Option<Vec<usize>> {
+    let trap_frame  = process.get_trap_frame();
+    let buf         = trap_frame.a3;
+    let count       = trap_frame.a5;
+    if count == 0 || count > SERVICE_SELECT_LIMIT { return None; }
+
+    let len = count * size_of::<usize>();
+    process.address_check([buf, len], PTEFlag::RO);
+    let mut bytes : Vec<u8> = Vec::new();
+    bytes.resize(len, 0);
+    process.get_satp().user_to_core(SliceIter::new_vec(&mut bytes), buf, len);
+
+    let ports : Vec<usize> = bytes.chunks_exact(size_of::<usize>())
+        .map(|x| usize::from_ne_bytes(x.try_into().unwrap())).collect();
+    for (i, port) in ports.iter().enumerate() {
+        if ports[..i].contains(port) { return None; }
+        if !process.get_caps().has(*port, CAP_RECEIVE) { return None; }
+        if !process.service_can_receive(*port) { return None; }
+    }
+    return Some(ports);
+}
+
+/** Read the service name in a0 and a1 into kernel. */
+unsafe fn read_service_name(process : &mut Process) -> Option<Box<[u8]>> {
+    let trap_frame  = process.get_trap_frame();
+    let buf         = trap_frame.a0;
+    let len         = trap_frame.a1;
+    if len == 0 || len > SERVICE_NAME_LIMIT { return None; }
+    process.address_check([buf, len], PTEFlag::RO);
+    let mut name : Vec<u8> = Vec::new();
+    name.resize(len, 0);
+    process.get_satp().user_to_core(SliceIter::new_vec(&mut name), buf, len);
+    return Some(name.into_boxed_slice());
+}
+
+/** Copy %items out to the user array at %buf. */
+pub(super) unsafe fn copy_out<T : Copy>(process : &mut Process, buf : usize, items : &[T]) {
+    let len = items.len() * size_of::<T>();
+    if len == 0 { return; }
+    process.address_check([buf, len], PTEFlag::WO);
+    let mut bytes = core::slice::from_raw_parts(items.as_ptr() as *const u8, len).to_vec();
+    process.get_satp().core_to_user(buf, len, SliceIter::new_vec(&mut bytes));
 }
@@ -1,11 +1,157 @@
-use crate::cpu::CPU;
+extern crate alloc;
+use alloc::sync::Arc;
+use sys::syscall::{CAP_RECEIVE, PM_PORT, PROT_EXEC, PROT_READ, PROT_WRITE, SERVICE_DENIED, SERVICE_ERROR, SHM_SIZE_LIMIT};
+
+use crate::{alloc::{PTEFlag, SharedPages, PAGE_SIZE}, cpu::CPU, proc::set_oom_policy, service::KernelObject};
 
 impl CPU {
+    /** Move the break by a0. Return the old one, or SERVICE_ERROR if too much. */
     pub unsafe fn sys_sbrk(&mut self) {
         let process     = &mut *self.get_process();
         let trap_frame  = process.get_trap_frame();
         let increment   = trap_frame.a0 as isize;
-        let result      = process.get_memory_area().sbrk(increment);
-        process.get_trap_frame().a0 = result as usize;
+        let limits      = *process.get_limits();
+        let result      = process.get_memory_area().sbrk(increment, &limits);
+        process.get_trap_frame().a0 = result.unwrap_or(SERVICE_ERROR);
+    }
+
+    /**
+     * Map a1 bytes of anonymous private memory with a2 (PROT_*),
+     * preferably at a0. Return the address, or SERVICE_ERROR.
+     */
+    pub unsafe fn sys_mmap(&mut self) {
+        let process     = &mut *self.get_process();
+        let trap_frame  = process.get_trap_frame();
+        let (hint, len, prot) = (trap_frame.a0, trap_frame.a1, trap_frame.a2);
+        let limits      = *process.get_limits();
+        let result      = match prot_to_flag(prot) {
+            Some(flag)  => process.get_memory_area().mmap(hint, len, flag, &limits),
+            None        => None,
+        };
+        process.get_trap_frame().a0 = result.unwrap_or(SERVICE_ERROR);
+    }
+
+    /** Unmap the pages of [a0, a0 + a1). Return 0 or SERVICE_ERROR. */
+    pub unsafe fn sys_munmap(&mut self) {
+        let process     = &mut *self.get_process();
+        let trap_frame  = process.get_trap_frame();
+        let (start, len) = (trap_frame.a0, trap_frame.a1);
+        let result      = process.get_memory_area().munmap(start, len);
+        process.get_trap_frame().a0 = if result { 0 } else { SERVICE_ERROR };
+    }
+
+    /** Change the pages of [a0, a0 + a1) to a2 (PROT_*). Return 0 or SERVICE_ERROR. */
+    pub unsafe fn sys_mprotect(&mut self) {
+        let process     = &mut *self.get_process();
+        let trap_frame  = process.get_trap_frame();
+        let (start, len, prot) = (trap_frame.a0, trap_frame.a1, trap_frame.a2);
+        let result      = match prot_to_flag(prot) {
+            Some(flag)  => process.get_memory_area().mprotect(start, len, flag),
+            None        => false,
+        };
+        process.get_trap_frame().a0 = if result { 0 } else { SERVICE_ERROR };
+    }
+
+    /**
+     * Create a zero-filled shared memory object of a0 bytes.
+     * Return its local handle, or SERVICE_ERROR if the size is invalid,
+     * out of memory, or too many handles are held.
+     * It can be moved to others in a message of the handle mode.
+     */
+    pub unsafe fn sys_shm_create(&mut self) {
+        let process     = &mut *self.get_process();
+        let size        = process.get_trap_frame().a0;
+        if size == 0 || size > SHM_SIZE_LIMIT || !process.can_hold_handle() {
+            process.get_trap_frame().a0 = SERVICE_ERROR;
+            return;
+        }
+        let pages   = match SharedPages::new((size + PAGE_SIZE - 1) / PAGE_SIZE) {
+            Ok(pages)   => pages,
+            Err(_)      => {
+                process.get_trap_frame().a0 = SERVICE_ERROR;
+                return;
+            },
+        };
+        let object  = KernelObject::Shm(Arc::new(pages));
+        process.get_trap_frame().a0 = process.get_handles().insert(object);
+    }
+
+    /**
+     * Map the shared memory of local handle a0 with a1 (PROT_*).
+     * Return the address, or SERVICE_ERROR if not shared memory,
+     * the protection is invalid, or no space (or memory) to map,
+     * or beyond the limit of pages.
+     */
+    pub unsafe fn sys_shm_map(&mut self) {
+        let process     = &mut *self.get_process();
+        let trap_frame  = process.get_trap_frame();
+        let (index, prot) = (trap_frame.a0, trap_frame.a1);
+        let pages = match process.get_handles().get(index) {
+            Some(KernelObject::Shm(pages)) => Some(pages.clone()),
+            _ => None,
+        };
+        // The mapping holds its own references to the pages.
+        let limits = *process.get_limits();
+        let result = match (pages, prot_to_flag(prot)) {
+            (Some(pages), Some(flag)) => process.get_memory_area().share_map(pages.get_pages(), flag, &limits),
+            _ => None,
+        };
+        process.get_trap_frame().a0 = result.unwrap_or(SERVICE_ERROR);
+    }
+
+    /** Unmap the shared memory mapped at a0. Return 0 or SERVICE_ERROR. */
+    pub unsafe fn sys_shm_unmap(&mut self) {
+        let process     = &mut *self.get_process();
+        let address     = process.get_trap_frame().a0;
+        let result      = process.get_memory_area().share_unmap(address);
+        process.get_trap_frame().a0 = if result { 0 } else { SERVICE_ERROR };
+    }
+
+    /**
+     * Drop the shared memory of local handle a0. The pages are freed
+     * when no one else holds it, and no mapping is left.
+     */
+    pub unsafe fn sys_shm_destroy(&mut self) {
+        let process     = &mut *self.get_process();
+        let index       = process.get_trap_frame().a0;
+        let handles     = process.get_handles();
+        let result      = matches!(handles.get(index), Some(KernelObject::Shm(_)));
+        if result { handles.take(index); }
+        process.get_trap_frame().a0 = if result { 0 } else { SERVICE_ERROR };
+    }
+
+    /**
+     * Set how to choose the process killed when out of memory, to
+     * a0 (OOM_KILL_*). Return the old one, or SERVICE_ERROR if invalid.
+     * Only for the process manager.
+     */
+    pub unsafe fn sys_oom_policy(&mut self) {
+        let process     = &mut *self.get_process();
+        let policy      = process.get_trap_frame().a0;
+        if !process.get_caps().has(PM_PORT, CAP_RECEIVE) {
+            process.get_trap_frame().a0 = SERVICE_DENIED;
+            return;
+        }
+        process.get_trap_frame().a0 = set_oom_policy(policy).unwrap_or(SERVICE_ERROR);
+    }
+}
+
+/**
+ * Page flags of %prot (PROT_*). Writable and executable is not allowed.
+ * PROT_NONE is deliberately unsupported: a valid entry without R, W or X
+ * points to the next level, so such pages would have to be unmapped, and
+ * mprotect would then drop their contents.
+ */
+fn prot_to_flag(prot : usize) -> Option<PTEFlag> {
+    let read    = prot & PROT_READ  != 0;
+    let write   = prot & PROT_WRITE != 0;
+    let exec    = prot & PROT_EXEC  != 0;
+    if prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 { return None; }
+    match (read, write, exec) {
+        (_, true, false)        => Some(PTEFlag::RW),
+        (true, false, false)    => Some(PTEFlag::RO),
+        (true, false, true)     => Some(PTEFlag::RX),
+        (false, false, true)    => Some(PTEFlag::XO),
+        _ => None,
     }
 }
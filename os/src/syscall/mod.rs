@@ -26,6 +26,30 @@ impl CPU {
             SYS_REQUEST     => self.sys_request(),
             SYS_RECEIVE     => self.sys_receive(),
             SYS_RESPOND     => self.sys_respond(),
+            SYS_REQUEST_ASYNC => self.sys_request_async(),
+            SYS_COMPLETE    => self.sys_complete(),
+            SYS_REGISTER    => self.sys_register(),
+            SYS_LOOKUP      => self.sys_lookup(),
+            SYS_UNREGISTER  => self.sys_unregister(),
+            SYS_RECEIVE_ANY => self.sys_receive_any(),
+            SYS_RESTRICT    => self.sys_restrict(),
+            SYS_HANDLE_PORT => self.sys_handle_port(),
+            SYS_HANDLE_REPLY => self.sys_handle_reply(),
+            SYS_HANDLE_CLOSE => self.sys_handle_close(),
+            SYS_REPLY_RECEIVE => self.sys_reply_and_receive(),
+            SYS_IPC_STAT    => self.sys_ipc_stat(),
+            SYS_IPC_TRACE   => self.sys_ipc_trace(),
+            SYS_SHM_CREATE  => self.sys_shm_create(),
+            SYS_SHM_MAP     => self.sys_shm_map(),
+            SYS_SHM_UNMAP   => self.sys_shm_unmap(),
+            SYS_SHM_DESTROY => self.sys_shm_destroy(),
+            SYS_MMAP        => self.sys_mmap(),
+            SYS_MUNMAP      => self.sys_munmap(),
+            SYS_MPROTECT    => self.sys_mprotect(),
+            SYS_OOM_POLICY  => self.sys_oom_policy(),
+            SYS_GETRLIMIT   => self.sys_getrlimit(),
+            SYS_SETRLIMIT   => self.sys_setrlimit(),
+            SYS_PROC_STAT   => self.sys_proc_stat(),
             SYS_READ        => self.sys_read(),
             SYS_WRITE       => self.sys_write(),
             SYS_FORK        => self.sys_fork(),
@@ -1,6 +1,7 @@
-use sys::syscall::ARGS_BUFFERED;
+use sys::syscall::{is_valid_limit, ARGS_BUFFERED, CAP_RECEIVE, PM_PORT, SERVICE_DENIED, SERVICE_ERROR};
 
-use crate::{alloc::{CheckError, PTEFlag}, cpu::CPU, proc::{current_cpu, Process}, service::Argument};
+use crate::{alloc::{CheckError, OutOfMemory, PTEFlag}, cpu::CPU, proc::{current_cpu, process_stat, Process}, service::Argument};
+use super::ipc::copy_out;
 
 impl Process {
     pub unsafe fn address_check(&mut self, args : [usize; 2], permission : PTEFlag) {
@@ -11,7 +12,8 @@ impl Process {
                 CheckError::MissingPage(addr) => {
                     warning!("address check fail at {:x}", addr);
 
-                    if permission.contains(PTEFlag::RW) {
+                    // A write may hit a page to be copied on write.
+                    if permission.contains(PTEFlag::WO) {
                         self.handle_page_fault(addr, crate::trap::PageFaultType::Store)
                     } else {
                         self.handle_page_fault(addr, crate::trap::PageFaultType::Load)
@@ -21,8 +23,17 @@ impl Process {
         }
     }
 
-    unsafe fn fork(&mut self) -> Process {
-        let child = Process::init();
+    unsafe fn fork(&mut self) -> Result<(), OutOfMemory> {
+        let mut child = Process::init()?;
+
+        /* Copy the address space to children. */
+        if let Err(error) = child.get_memory_area().fork_from(self.get_memory_area()) {
+            child.discard();
+            return Err(error);
+        }
+
+        /* Store it first, so nothing fails after the manager knows it. */
+        let child = &mut *current_cpu().get_manager().store_process(child)?;
 
         /* Request to make a new child at children manager. */
         use sys::syscall::*;
@@ -36,13 +47,18 @@ impl Process {
         trap_frame.a0 = child.get_pid().bits();
         child.get_trap_frame().a0 = 0;
 
-        /* Copy the page take to children. */
-        child.get_satp().copy_from(self.get_satp());
-        return child;
+        /* Children inherit the rights on ports, the handles and the limits. */
+        *child.get_caps() = self.get_caps().clone();
+        *child.get_handles() = self.get_handles().fork();
+        *child.get_limits() = *self.get_limits();
+        current_cpu().get_manager().start_process(child);
+        return Ok(());
     }
 
     unsafe fn exit(&mut self, status: usize) -> ! {
         use sys::syscall::*;
+        self.service_cancel();
+        self.service_abandon();
         self.service_request(Argument::Register(status, 0), PM_EXIT, PM_PORT);
         current_cpu().get_manager().remove_process(self);
         self.yield_to_scheduler();
@@ -69,8 +85,11 @@ impl Process {
         panic!("invalid response from PM_WAIT");
     }
 
+    /** Return SERVICE_ERROR in a0 if the program cannot be loaded. */
     unsafe fn exec(&mut self, name : &[u8]) {
-        self.exec_test(name);
+        if !self.exec_test(name) {
+            self.get_trap_frame().a0 = SERVICE_ERROR;
+        }
     }
 
     pub unsafe fn handle_fatal_error(&mut self, msg: &str) -> ! {
@@ -84,9 +103,12 @@ impl CPU {
         return self.process_yield();
     }
 
+    /** Return SERVICE_ERROR if out of memory. */
     pub(super) unsafe fn sys_fork(&mut self) {
         let process     = &mut *self.get_process();
-        self.get_manager().insert_process(process.fork());
+        if process.fork().is_err() {
+            process.get_trap_frame().a0 = SERVICE_ERROR;
+        }
     }
 
     pub(super) unsafe fn sys_exit(&mut self) -> ! {
@@ -113,4 +135,51 @@ impl CPU {
             _ => panic!("Impossible argument type")
         }
     }
+    /** Return the limit of resource a0 (RLIMIT_*), or SERVICE_ERROR if invalid. */
+    pub(super) unsafe fn sys_getrlimit(&mut self) {
+        let process     = &mut *self.get_process();
+        let resource    = process.get_trap_frame().a0;
+        let result      = process.get_limits().get(resource);
+        process.get_trap_frame().a0 = result.unwrap_or(SERVICE_ERROR);
+    }
+
+    /**
+     * Set the limit of resource a0 (RLIMIT_*) to a1. Return the old one,
+     * or SERVICE_ERROR if invalid (including limits above RLIMIT_INFINITY).
+     * Only the process manager may raise it.
+     */
+    pub(super) unsafe fn sys_setrlimit(&mut self) {
+        let process     = &mut *self.get_process();
+        let trap_frame  = process.get_trap_frame();
+        let (resource, limit) = (trap_frame.a0, trap_frame.a1);
+        let result = match process.get_limits().get(resource) {
+            None => SERVICE_ERROR,
+            Some(_) if !is_valid_limit(limit) => SERVICE_ERROR,
+            Some(old) if limit > old && !process.get_caps().has(PM_PORT, CAP_RECEIVE) => SERVICE_DENIED,
+            Some(_) => process.get_limits().set(resource, limit).unwrap(),
+        };
+        process.get_trap_frame().a0 = result;
+    }
+
+    /**
+     * Read the usage of the first process with pid no less than a0 into
+     * the ProcStat at a1. Return that pid, or SERVICE_ERROR if no more.
+     * Others than the process manager may only read their own.
+     */
+    pub(super) unsafe fn sys_proc_stat(&mut self) {
+        let process     = &mut *self.get_process();
+        let trap_frame  = process.get_trap_frame();
+        let (pid, buf)  = (trap_frame.a0, trap_frame.a1);
+        if pid != process.get_pid().bits() && !process.get_caps().has(PM_PORT, CAP_RECEIVE) {
+            process.get_trap_frame().a0 = SERVICE_DENIED;
+            return;
+        }
+        process.get_trap_frame().a0 = match process_stat(pid) {
+            Some((pid, stat)) => {
+                copy_out(process, buf, &[stat]);
+                pid
+            },
+            None => SERVICE_ERROR,
+        };
+    }
 }
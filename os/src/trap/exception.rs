@@ -1,6 +1,6 @@
-use crate::proc::Process;
+use crate::{alloc::OutOfMemory, proc::Process};
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum PageFaultType {
     Load,
     Store,
@@ -9,9 +9,16 @@ pub enum PageFaultType {
 
 impl Process {
     pub unsafe fn handle_page_fault(&mut self, addr: usize, tp : PageFaultType) {
-        if !self.get_memory_area().handle_page_fault(addr, tp) {
-            warning!("Page fault at 0x{:x}", addr);
-            self.handle_fatal_error("");
+        let limits = *self.get_limits();
+        loop {
+            match self.get_memory_area().handle_page_fault(addr, tp, &limits) {
+                Ok(true) => return,
+                Ok(false) => break,
+                // Try again after some memory is dropped, if not killed.
+                Err(OutOfMemory) => self.handle_out_of_memory(),
+            }
         }
+        warning!("Page fault at 0x{:x}", addr);
+        self.handle_fatal_error("");
     }
 }
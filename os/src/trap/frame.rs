@@ -2,7 +2,7 @@ use core::ptr::null_mut;
 
 use riscv::register::satp;
 
-use crate::alloc::{PTEFlag, PageAddress, KERNEL_SATP, PAGE_SIZE};
+use crate::alloc::{OutOfMemory, PTEFlag, PageAddress, KERNEL_SATP, PAGE_SIZE};
 
 use super::{user_trap, TRAP_FRAME};
 
@@ -61,6 +61,10 @@ impl TrapFrame {
         let stack_top = self.kernel_stack;
         FRAME_ALLOCATOR.deallocate(stack_top);
     }
+    /** The top of the kernel stack. */
+    pub fn get_kernel_stack(&self) -> usize {
+        return self.kernel_stack;
+    }
     pub unsafe fn debug(&self) {
         message!("    sepc = {:#x}", self.pc);
         message!("    satp = {:#x}", self.kernel_satp);
@@ -76,20 +80,37 @@ struct FrameAllocator {
 static mut FRAME_ALLOCATOR : FrameAllocator = FrameAllocator::new();
 
 impl PageAddress {
-    /** Create a trap frame with all the private members initialized. */
-    pub unsafe fn map_trap_frame(&self) -> (&'static mut TrapFrame, usize) {
-        let trap_frame = self.new_smap(TRAP_FRAME, PTEFlag::RW);
-        let trap_frame = &mut *(trap_frame.address() as *mut TrapFrame);
-
-        let kernel_stack = FRAME_ALLOCATOR.allocate();
+    /**
+     * Create a trap frame with all the private members initialized.
+     * If out of memory, the kernel stack is given back.
+     */
+    pub unsafe fn map_trap_frame(&self) -> Result<(&'static mut TrapFrame, usize), OutOfMemory> {
+        let kernel_stack = FRAME_ALLOCATOR.allocate()?;
         let kernel_stack_top = kernel_stack + PAGE_SIZE;
 
+        match self.map_trap_frame_with(kernel_stack_top) {
+            Ok(trap_frame) => return Ok((trap_frame, kernel_stack_top)),
+            Err(error) => {
+                FRAME_ALLOCATOR.deallocate(kernel_stack_top);
+                return Err(error);
+            },
+        }
+    }
+
+    /**
+     * Create a trap frame on the kernel stack of %kernel_stack_top,
+     * e.g. the one in use on exec. The stack is kept if out of memory.
+     */
+    pub unsafe fn map_trap_frame_with(&self, kernel_stack_top : usize) -> Result<&'static mut TrapFrame, OutOfMemory> {
+        let trap_frame = self.new_smap(TRAP_FRAME, PTEFlag::RW)?;
+        let trap_frame = &mut *(trap_frame.address() as *mut TrapFrame);
+
         trap_frame.thread_number = 0;
         trap_frame.kernel_stack = kernel_stack_top;
         trap_frame.kernel_satp  = satp::read().bits();
         trap_frame.kernel_trap  = user_trap as _;
 
-        return (trap_frame, kernel_stack_top);
+        return Ok(trap_frame);
     }
 }
 
@@ -103,17 +124,21 @@ impl FrameAllocator {
         }
     }
 
-    pub unsafe fn allocate(&mut self) -> usize {
+    pub unsafe fn allocate(&mut self) -> Result<usize, OutOfMemory> {
         if self.last == null_mut() {
+            let result = self.lowest - PAGE_SIZE;
+            // A page mapped by a failed attempt is kept for the next one.
+            for page in [result - PAGE_SIZE, result] {
+                if !KERNEL_SATP.is_umapped(page) {
+                    KERNEL_SATP.new_smap(page, PTEFlag::RW)?;
+                }
+            }
             self.lowest -= PAGE_SIZE * 4;
-            let result = self.lowest + 3 * PAGE_SIZE;
-            KERNEL_SATP.new_smap(result - PAGE_SIZE, PTEFlag::RW);
-            KERNEL_SATP.new_smap(result, PTEFlag::RW);
-            return result;
+            return Ok(result);
         } else {
             let result = self.last;
             self.last = *self.last as *mut usize;
-            return result as _;
+            return Ok(result as _);
         }
     }
 
@@ -1,6 +1,6 @@
 use core::arch::asm;
 use riscv::register::*;
-use crate::{driver::plic, proc::current_cpu, trap::{set_dead_trap, set_kernel_trap}};
+use crate::{driver::plic, proc::current_cpu, service::service_check_timeout, trap::{set_dead_trap, set_kernel_trap}};
 
 #[no_mangle]
 unsafe fn core_trap() {
@@ -14,6 +14,7 @@ unsafe fn core_trap() {
             Interrupt::SupervisorSoft => {
                 current_cpu().reset_timer_time();
                 asm!("csrci sip, 2");
+                service_check_timeout();
             },
             Interrupt::SupervisorExternal => {
                 plic::resolve();
@@ -8,7 +8,7 @@ pub use user::*;
 pub use frame::TrapFrame;
 
 use riscv::register::*;
-use crate::alloc::{PTEFlag, PageAddress, PAGE_SIZE};
+use crate::alloc::{OutOfMemory, PTEFlag, PageAddress, PAGE_SIZE};
 
 core::arch::global_asm!(include_str!("trap.asm"));
 
@@ -46,8 +46,8 @@ unsafe fn get_trampoline_physical() -> PageAddress {
 }
 
 impl PageAddress {
-    pub unsafe fn map_trampoline(self) {
-        self.smap(TRAMPOLINE, get_trampoline_physical(), PTEFlag::RX);
+    pub unsafe fn map_trampoline(self) -> Result<(), OutOfMemory> {
+        return self.smap(TRAMPOLINE, get_trampoline_physical(), PTEFlag::RX);
     }
 }
 
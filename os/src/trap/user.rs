@@ -3,6 +3,7 @@ use riscv::register::*;
 use crate::alloc::PageAddress;
 use crate::cpu::current_cpu;
 use crate::driver::plic;
+use crate::service::service_check_timeout;
 use crate::trap::exception::PageFaultType;
 use crate::trap::{set_kernel_trap, set_user_trap};
 use super::{user_handle, user_return, Interrupt, TRAMPOLINE};
@@ -32,6 +33,7 @@ pub unsafe fn user_trap() {
             // We should yield out the time.
             Interrupt::SupervisorSoft => {
                 asm!("csrci sip, 2");
+                service_check_timeout();
                 process.yield_to_scheduler();
             },
             Interrupt::SupervisorExternal => {
@@ -64,6 +66,9 @@ pub unsafe fn user_trap() {
         }
     }
 
+    // Chosen as the victim of out of memory.
+    if process.is_doomed() { process.handle_doom(); }
+
     // TODO: Load the satp register of the user
     return user_trap_return();
 }
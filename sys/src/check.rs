@@ -0,0 +1,130 @@
+/* Pure checks done by the kernel, kept here to be tested on the host. */
+
+use crate::syscall::{PROT_EXEC, PROT_READ, PROT_WRITE};
+
+const PAGE_SIZE : usize = 4096;
+
+/** A loadable segment of a program, as [start, start + size). */
+#[derive(Clone, Copy)]
+pub struct Segment {
+    pub start   : usize,    // Start of the segment
+    pub size    : usize,    // Size of the segment in memory
+    pub prot    : usize,    // Rights of the pages (PROT_*)
+}
+
+/**
+ * Rights (PROT_*) of a segment with the given permission bits.
+ * Writable pages are made readable, and writable and executable
+ * at the same time is not allowed.
+ */
+pub fn segment_prot(read : bool, write : bool, exec : bool) -> Result<usize, &'static str> {
+    match (read, write, exec) {
+        (_, true, true)         => Err("writable and executable segment"),
+        (_, true, false)        => Ok(PROT_READ | PROT_WRITE),
+        (true, false, false)    => Ok(PROT_READ),
+        (true, false, true)     => Ok(PROT_READ | PROT_EXEC),
+        (false, false, true)    => Ok(PROT_EXEC),
+        (false, false, false)   => Err("segment without permission"),
+    }
+}
+
+/** Pages of [%start, %start + %size), as (first, end). */
+fn page_range(start : usize, size : usize) -> (usize, usize) {
+    (start / PAGE_SIZE, (start + size).div_ceil(PAGE_SIZE))
+}
+
+/**
+ * Check that no segments overlap, and that no page shared by two
+ * segments ends up both writable and executable.
+ */
+pub fn check_segments(segments : &[Segment]) -> Result<(), &'static str> {
+    for (i, x) in segments.iter().enumerate() {
+        for y in segments[i + 1..].iter() {
+            if x.start < y.start + y.size && y.start < x.start + x.size {
+                return Err("overlapping segments");
+            }
+            let (x_first, x_end) = page_range(x.start, x.size);
+            let (y_first, y_end) = page_range(y.start, y.size);
+            if x_first >= y_end || y_first >= x_end { continue; }
+            let prot = x.prot | y.prot;
+            if prot & PROT_WRITE != 0 && prot & PROT_EXEC != 0 {
+                return Err("segments with conflicting rights");
+            }
+        }
+    }
+    Ok(())
+}
+
+/**
+ * The break moved by %increment from %old, if within [%floor, %ceiling].
+ * Return None if it would wrap around, or go out of the bounds.
+ */
+pub fn move_break(old : usize, increment : isize, floor : usize, ceiling : usize) -> Option<usize> {
+    let new = match increment < 0 {
+        true    => old.checked_sub(increment.unsigned_abs())?,
+        false   => old.checked_add(increment as usize)?,
+    };
+    if new < floor || new > ceiling { return None; }
+    Some(new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(start : usize, size : usize, prot : usize) -> Segment {
+        Segment { start, size, prot }
+    }
+
+    #[test]
+    fn writable_and_executable_rejected() {
+        assert!(segment_prot(false, true, true).is_err());
+        assert!(segment_prot(true, true, true).is_err());
+        assert!(segment_prot(false, false, false).is_err());
+        assert_eq!(segment_prot(true, false, true), Ok(PROT_READ | PROT_EXEC));
+        assert_eq!(segment_prot(false, true, false), Ok(PROT_READ | PROT_WRITE));
+        assert_eq!(segment_prot(false, false, true), Ok(PROT_EXEC));
+    }
+
+    #[test]
+    fn overlapping_segments_rejected() {
+        let code = segment(0x1000, 0x2000, PROT_READ | PROT_EXEC);
+        let data = segment(0x2000, 0x1000, PROT_READ);
+        assert!(check_segments(&[code, data]).is_err());
+        let data = segment(0x3000, 0x1000, PROT_READ);
+        assert!(check_segments(&[code, data]).is_ok());
+    }
+
+    #[test]
+    fn shared_page_must_not_be_writable_and_executable() {
+        let code = segment(0x1000, 0x800, PROT_READ | PROT_EXEC);
+        let data = segment(0x1800, 0x800, PROT_READ | PROT_WRITE);
+        assert!(check_segments(&[code, data]).is_err());
+
+        let data = segment(0x1000 + PAGE_SIZE, 0x800, PROT_READ | PROT_WRITE);
+        assert!(check_segments(&[code, data]).is_ok());
+
+        let rodata = segment(0x1800, 0x800, PROT_READ);
+        assert!(check_segments(&[code, rodata]).is_ok());
+    }
+
+    #[test]
+    fn break_moves_within_heap() {
+        assert_eq!(move_break(0x2000, 0x1000, 0x1000, 0x8000), Some(0x3000));
+        assert_eq!(move_break(0x2000, -0x1000, 0x1000, 0x8000), Some(0x1000));
+        assert_eq!(move_break(0x2000, 0, 0x1000, 0x8000), Some(0x2000));
+        assert_eq!(move_break(0x2000, 0x6000, 0x1000, 0x8000), Some(0x8000));
+    }
+
+    #[test]
+    fn break_out_of_heap() {
+        assert_eq!(move_break(0x2000, -0x1001, 0x1000, 0x8000), None);
+        assert_eq!(move_break(0x2000, 0x6001, 0x1000, 0x8000), None);
+    }
+
+    #[test]
+    fn break_never_wraps() {
+        assert_eq!(move_break(0x2000, isize::MIN, 0x1000, 0x8000), None);
+        assert_eq!(move_break(usize::MAX - 1, isize::MAX, 0, usize::MAX), None);
+    }
+}
@@ -1,3 +1,4 @@
 #![no_std]
 
 pub mod syscall;
+pub mod check;
@@ -18,16 +18,44 @@ pub const SYS_SBRK      : usize   = 13;
 pub const SYS_GETPID    : usize   = 14;
 pub const SYS_KILL      : usize   = 15;
 
+pub const SYS_REQUEST_ASYNC : usize = 16;
+pub const SYS_COMPLETE      : usize = 17;
+
+pub const SYS_REGISTER      : usize = 18;
+pub const SYS_LOOKUP        : usize = 19;
+pub const SYS_UNREGISTER    : usize = 20;
+pub const SYS_RECEIVE_ANY   : usize = 21;
+pub const SYS_RESTRICT      : usize = 22;
+pub const SYS_HANDLE_PORT   : usize = 23;
+pub const SYS_HANDLE_REPLY  : usize = 24;
+pub const SYS_HANDLE_CLOSE  : usize = 25;
+pub const SYS_REPLY_RECEIVE : usize = 26;
+pub const SYS_IPC_STAT      : usize = 27;
+pub const SYS_IPC_TRACE     : usize = 28;
+pub const SYS_SHM_CREATE    : usize = 29;
+pub const SYS_SHM_MAP       : usize = 30;
+pub const SYS_SHM_UNMAP     : usize = 31;
+pub const SYS_SHM_DESTROY   : usize = 32;
+pub const SYS_MMAP          : usize = 33;
+pub const SYS_MUNMAP        : usize = 34;
+pub const SYS_MPROTECT      : usize = 35;
+pub const SYS_OOM_POLICY    : usize = 36;
+pub const SYS_GETRLIMIT     : usize = 37;
+pub const SYS_SETRLIMIT     : usize = 38;
+pub const SYS_PROC_STAT     : usize = 39;
+
 pub const SYS_EXEC_NO   : usize   = 100; // A debug use syscall
 
 pub const SYS_SHUTDOWN  : usize   = 114;
 
 pub const PM_PORT : usize = 0;
+pub const PM_NAME : &str  = "pm";
 pub const PM_EXIT : usize = 0;
 pub const PM_FORK : usize = 1;
 pub const PM_EXEC : usize = 2;
 pub const PM_WAIT : usize = 3;
 pub const PM_DUMP : usize = 9;
+pub const PM_USER : usize = 10; // Kinds below are sent by kernel only.
 
 pub const PM_MUTEX_CREATE   : usize = 10;
 pub const PM_MUTEX_DESTROY  : usize = 11;
@@ -40,10 +68,138 @@ pub const PM_COND_WAIT      : usize = 16;
 pub const PM_COND_SIGNAL    : usize = 17;
 pub const PM_COND_BROADCAST : usize = 18;
 
+pub const SERVICE_NAME_LIMIT : usize = 32;  // Max length of a service name.
+pub const SERVICE_SELECT_LIMIT : usize = 16; // Max ports to receive at once.
+pub const SHM_SIZE_LIMIT : usize = 1 << 24; // Max bytes of a shared memory object.
+
+pub const PROT_READ     : usize = 1; // Pages can be read
+pub const PROT_WRITE    : usize = 2; // Pages can be written
+pub const PROT_EXEC     : usize = 4; // Pages can be executed
+
+pub const OOM_KILL_FAULTING : usize = 0; // Kill the process out of memory
+pub const OOM_KILL_LARGEST  : usize = 1; // Kill the one with most pages
+
+pub const RLIMIT_HEAP       : usize = 0; // Bytes of the heap grown by sbrk
+pub const RLIMIT_STACK      : usize = 1; // Bytes of the stack
+pub const RLIMIT_PAGES      : usize = 2; // Resident pages in all
+pub const RLIMIT_HANDLES    : usize = 3; // Handles held at once
+pub const RLIMIT_COUNT      : usize = 4;
+pub const RLIMIT_INFINITY   : usize = isize::MAX as usize; // No limit, the largest valid one
+pub fn is_valid_limit(limit : usize) -> bool { limit <= RLIMIT_INFINITY } // Never an error code
+pub const SERVICE_ERROR : usize = !0;       // No such service, or name taken.
+pub const SERVICE_DEAD  : usize = !1;       // The servant has died.
+pub const SERVICE_TIMEOUT : usize = !2;     // The deadline has passed.
+pub const SERVICE_DENIED  : usize = !3;     // The right is not held.
+pub const SERVICE_NOTIFY : usize = 1;       // Flag to register: notify cancellation.
+pub const SERVICE_PORT_LIMIT : usize = 16;  // Ports owned by a process at once.
+pub const SERVICE_ASYNC_LIMIT : usize = 64; // Async requests of a process not taken yet.
+pub const IPC_CANCEL : usize = !0;          // Kind of cancellation notification.
+
 const MAGIC : usize = 1919;
+const TICKET_SHIFT : usize = 32; // Ticket of async request in the high bits.
+const SYNC_BIT : usize = 1 << 62; // Set for blocking requests, with a sequence as the ticket.
+pub const TICKET_LIMIT : usize = (1 << 30) - 1; // Tickets (and sequences) are in [1, TICKET_LIMIT].
+// Handles stay positive as isize, since negative ones mean failure on receive.
 
-pub unsafe fn pid_to_handle(x : usize) -> usize { x + MAGIC }
-pub unsafe fn handle_to_pid(x : usize) -> usize { x - MAGIC }
+// A forged handle decodes to a bogus pid, which the kernel then fails to find.
+pub fn pid_to_handle(x : usize) -> usize { x + MAGIC }
+pub fn handle_to_pid(x : usize) -> usize { (x & ((1 << TICKET_SHIFT) - 1)).wrapping_sub(MAGIC) }
+pub fn ticket_to_handle(x : usize, ticket : usize) -> usize { pid_to_handle(x) | ticket << TICKET_SHIFT }
+pub fn sequence_to_handle(x : usize, sequence : usize) -> usize { ticket_to_handle(x, sequence) | SYNC_BIT }
+pub fn handle_to_ticket(x : usize) -> usize { if x & SYNC_BIT != 0 { 0 } else { x >> TICKET_SHIFT } }
 
 pub const ARGS_REGISTER : usize = 0; // Argument * 2
 pub const ARGS_BUFFERED : usize = 1; // Buffer + Length
+pub const ARGS_UPOINTER : usize = 2; // Buffer + Length (zero-copy)
+pub const ARGS_MAPPING  : usize = 3; // Buffer + Length (lent pages)
+pub const ARGS_CAPABILITY : usize = 4; // Port + Rights
+pub const ARGS_HANDLE   : usize = 5; // Local handle (+ Value when received)
+pub const ARGS_WIDE     : usize = 6; // Argument * 6 (a0, a1, t1 - t4)
+
+pub const CAP_SEND      : usize = 1; // Right to request at a port
+pub const CAP_RECEIVE   : usize = 2; // Right to receive at a port
+
+pub const IPC_TRACE_KEEP : usize = 0;       // Leave the trace as it is.
+pub const IPC_TRACE_ON   : usize = 1;       // Start recording IPC events.
+pub const IPC_TRACE_OFF  : usize = 2;       // Stop recording IPC events.
+pub const IPC_TRACE_LIMIT : usize = 256;    // Events kept in the ring buffer.
+
+pub const IPC_EVENT_REQUEST : usize = 0;    // Queued at the port.
+pub const IPC_EVENT_RECEIVE : usize = 1;    // Taken by a servant.
+pub const IPC_EVENT_RESPOND : usize = 2;    // Responded by the servant.
+pub const IPC_EVENT_CANCEL  : usize = 3;    // Withdrawn before the response.
+
+/** Counters of a port, as returned by SYS_IPC_STAT. */
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct IpcStat {
+    pub requests    : usize,    // Requests queued
+    pub responses   : usize,    // Responses delivered
+    pub cancels     : usize,    // Requests cancelled
+    pub bytes       : usize,    // Bytes copied in buffers
+    pub depth       : usize,    // Requests waiting now
+    pub max_depth   : usize,    // Most requests ever waiting
+    pub latency     : usize,    // Total time from request to respond
+}
+
+/** An IPC event, as returned by SYS_IPC_TRACE. */
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct IpcEvent {
+    pub time    : usize,        // Timer value when it happens
+    pub event   : usize,        // IPC_EVENT_*
+    pub port    : usize,        // Where it happens
+    pub handle  : usize,        // Handle of the request
+}
+
+/** Resource usage of a process, as returned by SYS_PROC_STAT. */
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct ProcStat {
+    pub resident    : usize,    // Pages mapped, shared ones included
+    pub private     : usize,    // Pages owned by this process alone
+    pub heap        : usize,    // Bytes of the heap
+    pub stack       : usize,    // Bytes of the stack
+    pub handles     : usize,    // Handles held
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handles_keep_pid_and_ticket() {
+        for pid in [1, 2, 1000, (1 << 20) + 7] {
+            assert_eq!(handle_to_pid(pid_to_handle(pid)), pid);
+            assert_eq!(handle_to_ticket(pid_to_handle(pid)), 0);
+            for ticket in [1, 2, TICKET_LIMIT] {
+                let handle = ticket_to_handle(pid, ticket);
+                assert_eq!(handle_to_pid(handle), pid);
+                assert_eq!(handle_to_ticket(handle), ticket);
+                assert!((handle as isize) > 0);
+            }
+        }
+    }
+
+    #[test]
+    fn blocking_handles_have_no_ticket() {
+        for sequence in [1, 2, TICKET_LIMIT] {
+            let handle = sequence_to_handle(5, sequence);
+            assert_eq!(handle_to_pid(handle), 5);
+            assert_eq!(handle_to_ticket(handle), 0);
+            assert!((handle as isize) > 0);
+        }
+        // A late response to an older request never matches the next one.
+        assert_ne!(sequence_to_handle(5, 1), sequence_to_handle(5, 2));
+        assert_ne!(sequence_to_handle(5, 1), ticket_to_handle(5, 1));
+    }
+
+    #[test]
+    fn limits_are_not_errors() {
+        assert!(is_valid_limit(0));
+        assert!(is_valid_limit(RLIMIT_INFINITY));
+        for error in [SERVICE_ERROR, SERVICE_DEAD, SERVICE_TIMEOUT, SERVICE_DENIED] {
+            assert!(!is_valid_limit(error));
+        }
+    }
+}
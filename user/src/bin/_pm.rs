@@ -44,8 +44,8 @@ fn handle_async_request(argument : Argument, kind : IPCKind) {
 
 fn handle_fork(argument : Argument, handle: IPCHandle) {
     let (x0, _) = match argument {
-        Argument::Buffered(_, _) => panic!("Should not have any arguments!"),
-        Argument::Register(x0, x1) => (x0, x1) 
+        Argument::Register(x0, x1) => (x0, x1),
+        _ => panic!("Should not have any arguments!"),
     };
 
     let parent_pid = unsafe { handle.get_pid().bits() };
@@ -63,8 +63,8 @@ fn handle_fork(argument : Argument, handle: IPCHandle) {
 
 fn handle_exit(argument : Argument, handle: IPCHandle) {
     let (x0, _) = match argument {
-        Argument::Buffered(_, _) => panic!("Should not have any arguments!"),
-        Argument::Register(x0, x1) => (x0, x1) 
+        Argument::Register(x0, x1) => (x0, x1),
+        _ => panic!("Should not have any arguments!"),
     };
 
     let pid = unsafe { handle.get_pid().bits() };
@@ -90,13 +90,15 @@ fn handle_wait(argument : Argument, handle: IPCHandle) {
 fn process_dump(argument : Argument) {
     let _ = argument; // Unused
     println!("-- Received dump request --");
-    return pm_dump();
+    pm_dump();
+    proc_dump();
+    return ipc_dump();
 }
 
 fn mutex_create(argument : Argument, handle: IPCHandle) {
     let (_, _) = match argument {
-        Argument::Buffered(_, _) => panic!("Should not have any arguments!"),
-        Argument::Register(x0, x1) => (x0, x1) 
+        Argument::Register(x0, x1) => (x0, x1),
+        _ => panic!("Should not have any arguments!"),
     };
 
     let pid = unsafe { handle.get_pid().bits() };
@@ -110,8 +112,8 @@ fn mutex_create(argument : Argument, handle: IPCHandle) {
 
 fn mutex_destroy(argument : Argument, handle: IPCHandle) {
     let (x0, _) = match argument {
-        Argument::Buffered(_, _) => panic!("Should not have any arguments!"),
-        Argument::Register(x0, x1) => (x0, x1) 
+        Argument::Register(x0, x1) => (x0, x1),
+        _ => panic!("Should not have any arguments!"),
     };
 
     let pid = unsafe { handle.get_pid().bits() };
@@ -126,8 +128,8 @@ fn mutex_destroy(argument : Argument, handle: IPCHandle) {
 
 fn mutex_lock(argument : Argument, handle: IPCHandle) {
     let (x0, _) = match argument {
-        Argument::Buffered(_, _) => panic!("Should not have any arguments!"),
-        Argument::Register(x0, x1) => (x0, x1) 
+        Argument::Register(x0, x1) => (x0, x1),
+        _ => panic!("Should not have any arguments!"),
     };
 
     let pid = unsafe { handle.get_pid().bits() };
@@ -141,8 +143,8 @@ fn mutex_lock(argument : Argument, handle: IPCHandle) {
 
 fn mutex_unlock(argument : Argument, handle: IPCHandle) {
     let (x0, _) = match argument {
-        Argument::Buffered(_, _) => panic!("Should not have any arguments!"),
-        Argument::Register(x0, x1) => (x0, x1) 
+        Argument::Register(x0, x1) => (x0, x1),
+        _ => panic!("Should not have any arguments!"),
     };
 
     let pid = unsafe { handle.get_pid().bits() };
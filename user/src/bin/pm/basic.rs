@@ -1,10 +1,11 @@
 use core::ptr::null;
 
-use user_lib::{print, println, sys_respond, Argument, IPCHandle};
+use sys::syscall::{IpcEvent, IPC_TRACE_ON, IPC_TRACE_LIMIT};
+use user_lib::{print, println, sys_ipc_stat, sys_ipc_trace, sys_proc_stat, sys_respond, Argument, IPCHandle};
 use crate::{pm::POOL, Node};
 
 extern crate alloc;
-use alloc::vec::Vec;
+use alloc::{vec, vec::Vec};
 
 impl Node {
     pub fn insert_child(&mut self, child : &mut Node) {
@@ -117,3 +118,39 @@ pub fn pm_dump() {
     }
     println!("==    End of dump    ==");
 }
+
+/**
+ * Print the IPC counters of all ports, and the events traced since
+ * the last dump. The trace is turned on by the first dump.
+ */
+pub fn ipc_dump() {
+    println!("== Dumping IPC counters ==");
+    let mut next = 0;
+    while let Some((port, stat)) = sys_ipc_stat(next) {
+        let average = stat.latency / stat.responses.max(1);
+        println!("[port {}] requests {}, responses {}, cancels {}, bytes {}, depth {} (max {}), latency {} ticks",
+            port, stat.requests, stat.responses, stat.cancels, stat.bytes, stat.depth, stat.max_depth, average);
+        next = port + 1;
+    }
+
+    let mut events = vec![IpcEvent::default(); IPC_TRACE_LIMIT];
+    let count = sys_ipc_trace(&mut events, IPC_TRACE_ON);
+    for event in &events[..count] {
+        let name = ["request", "receive", "respond", "cancel"][event.event];
+        println!("  {:>12} {:<8} port {} handle {:#x}", event.time, name, event.port, event.handle);
+    }
+    println!("==    End of dump    ==");
+}
+
+/** Print the resource usage of all processes, like ps. */
+pub fn proc_dump() {
+    println!("== Dumping process usage ==");
+    println!("{:>5} {:>8} {:>8} {:>10} {:>10} {:>8}", "pid", "resident", "private", "heap", "stack", "handles");
+    let mut next = 1;
+    while let Some((pid, stat)) = sys_proc_stat(next) {
+        println!("{:>5} {:>8} {:>8} {:>10} {:>10} {:>8}",
+            pid, stat.resident, stat.private, stat.heap, stat.stack, stat.handles);
+        next = pid + 1;
+    }
+    println!("==    End of dump    ==");
+}
@@ -2,7 +2,7 @@ mod basic;
 mod thread;
 use core::ptr::null_mut;
 use crate::IPCHandle;
-pub use basic::pm_dump;
+pub use basic::{ipc_dump, pm_dump, proc_dump};
 
 extern crate alloc;
 use alloc::{collections::BTreeMap, vec::Vec, boxed::Box};
@@ -1,7 +1,13 @@
-use core::{cmp::max, sync::atomic::AtomicBool};
-use crate::{memory::{heap::get_heap, list::Header}, sys_sbrk};
+use core::{cmp::max, ptr::null_mut, sync::atomic::AtomicBool};
+use sys::syscall::{PROT_READ, PROT_WRITE, SERVICE_ERROR};
+use crate::{memory::{heap::get_heap, list::Header}, sys_mmap, sys_munmap, sys_sbrk};
 use super::{heap::Heap, list::Node};
 
+/** Blocks at least this large are put in their own mappings. */
+const MMAP_THRESHOLD : usize = 64 << 10;
+/** Previous size of a mapped block, never a real one (multiples of 8). */
+const MAPPED_SIZE : u32 = 1;
+
 unsafe fn malloc_init() {
     let heap_beg = sys_sbrk(4096) as usize;
     let heap_end = heap_beg + 4096;
@@ -12,19 +18,21 @@ unsafe fn malloc_init() {
 
 static mut INIT : AtomicBool = AtomicBool::new(false);
 
-unsafe fn enlarge(size : usize) {
+/** Grow the heap by at least %size bytes. Return false if out of memory. */
+unsafe fn enlarge(size : usize) -> bool {
     let size = (size + 4095) & !4095;
     let heap = get_heap();
-    return heap.enlarge(sys_sbrk(size as isize), size);
+    let sbrk = sys_sbrk(size as isize);
+    if sbrk as usize == SERVICE_ERROR { return false; }
+    heap.enlarge(sbrk, size);
+    return true;
 }
 
+/** Return null if out of memory. */
 unsafe fn try_find(heap : &mut Heap, size : usize) -> *mut Header {
     let header = heap.first_fit(size);
-    if header.is_null() {
-        enlarge(size);
-        let header = heap.first_fit(size);
-        assert!(!header.is_null(), "Out of memory");
-        return header;
+    if header.is_null() && enlarge(size) {
+        return heap.first_fit(size);
     } else {
         return header;
     }
@@ -37,8 +45,11 @@ pub unsafe fn malloc(size : usize) -> *mut u8 {
 
     const MIN_SIZE : usize = size_of::<Header>() + size_of::<Node>();
     let size = max((size + size_of::<Header>() + 7) & !7, MIN_SIZE);
+    if size >= MMAP_THRESHOLD { return malloc_mapped(size); }
+
     let heap = get_heap();
     let header = try_find(heap, size);
+    if header.is_null() { return null_mut(); }
 
     let (data, rest) = (*header).try_split(size);
     match rest {
@@ -49,6 +60,19 @@ pub unsafe fn malloc(size : usize) -> *mut u8 {
     return data;
 }
 
+/** Put a large block in its own mapping, so it is returned on free. */
+unsafe fn malloc_mapped(size : usize) -> *mut u8 {
+    let size = (size + 4095) & !4095;
+    assert!(size <= u32::MAX as usize & !4095, "Block too large");
+    let header = match sys_mmap(null_mut(), size, PROT_READ | PROT_WRITE) {
+        Some(header) => header as *mut Header,
+        None => return null_mut(),
+    };
+    (*header).set_prev_size(MAPPED_SIZE);
+    (*header).set_size_with(size as _, true);
+    return (*header).get_data();
+}
+
 unsafe fn check_initialized() {
     assert!(INIT.load(core::sync::atomic::Ordering::Relaxed), "Malloc not initialized");
 }
@@ -57,9 +81,13 @@ pub unsafe fn free(ptr : *mut u8) {
     if ptr.is_null() { return; }
     check_initialized();
     assert!(ptr as usize % 8 == 0, "Misaligned pointer");
+    let header = (ptr as *mut Header).sub(1);
+    if (*header).get_prev_size() == MAPPED_SIZE {
+        sys_munmap(header as *mut u8, (*header).get_size() as usize);
+        return;
+    }
     let heap = get_heap();
-    let header = ptr as *mut Header;
-    heap.recycle(header.sub(1));
+    heap.recycle(header);
 }
 
 pub unsafe fn malloc_usable_size(ptr : *mut u8) -> usize {
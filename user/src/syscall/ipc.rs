@@ -1,34 +1,102 @@
 use sys::syscall::*;
 
 use crate::PidType;
+use super::call::*;
 
 pub struct IPCHandle(usize);
 
 pub enum Argument {
     Register(usize, usize),     // In 2 registers.
     Buffered(*mut u8, usize),   // In a user buffer.
+    Upointer(*mut u8, usize),   // In a user buffer, without kernel copy.
+    Mapping(*mut u8, usize),    // In user pages, lent read-only to the servant.
+    Capability(usize, usize),   // Rights (CAP_*) on a port, granted to the receiver.
+    Handle(usize, usize),       // Local handle moved to the receiver (+ its value).
+    Wide([usize; 6]),           // In 6 registers.
 }
 
 pub type IPCKind = usize;
 
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct IPCTicket(usize);
+
 pub enum IPCEnum {
     IPCFail(usize),                 // Buffer too small, provide the needed size.
+    IPCError,                       // No such port, or no right to receive.
+    IPCTimeout,                     // No request before the deadline.
     IPCAsync(Argument, IPCKind),    // Asynchronous IPC.
     IPCHandle(Argument, IPCKind, IPCHandle), // With argument and handle.
 }
 
 pub struct AcceptPacket {
     args    : [usize; 3],
+    wide    : [usize; 4],
     kind    : usize,
     result  : isize,
+    port    : usize,
 }
 
 pub fn sys_request(args : Argument, port : usize, kind : usize) -> isize {
-    let mut ret : isize;
-    let args = match args {
-        Argument::Register(a0, a1) => [a0, a1, ARGS_REGISTER],
-        Argument::Buffered(a0, a1) => [a0 as usize, a1, ARGS_BUFFERED],
-    };
+    return request_impl(args, port, kind, [0, 0], 0);
+}
+
+/**
+ * Request with a buffer to hold the response from the servant.
+ * Return the length of the response, or the negative length
+ * of the whole response if the buffer is too small (truncated).
+ */
+pub fn sys_request_buffered(args : Argument, port : usize, kind : usize, buf : &mut [u8]) -> isize {
+    return request_impl(args, port, kind, [buf.as_mut_ptr() as usize, buf.len()], 0);
+}
+
+/**
+ * Request with a timeout in milliseconds (0 for none).
+ * Return SERVICE_TIMEOUT (as isize) if no response in time.
+ */
+pub fn sys_request_timeout(args : Argument, port : usize, kind : usize, buf : &mut [u8], timeout : usize) -> isize {
+    return request_impl(args, port, kind, [buf.as_mut_ptr() as usize, buf.len()], timeout);
+}
+
+/** Request, and return the response in 6 words (for Argument::Wide). */
+pub fn sys_request_wide(args : Argument, port : usize, kind : usize) -> [usize; 6] {
+    return wide_impl(args, port, kind, [0, 0], 0);
+}
+
+fn request_impl(args : Argument, port : usize, kind : usize, buffer : [usize; 2], timeout : usize) -> isize {
+    return wide_impl(args, port, kind, buffer, timeout)[0] as isize;
+}
+
+fn wide_impl(args : Argument, port : usize, kind : usize, buffer : [usize; 2], timeout : usize) -> [usize; 6] {
+    let mut ret = args.to_registers();
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") ret[0],
+            inlateout("a1") ret[1],
+            in("a2") ret[2],
+            in("a3") buffer[0],
+            in("a4") kind,
+            in("a5") buffer[1],
+            in("a6") port,
+            in("a7") SYS_REQUEST,
+            in("t0") timeout,
+            inlateout("t1") ret[3],
+            inlateout("t2") ret[4],
+            inlateout("t3") ret[5],
+            inlateout("t4") ret[6],
+        );
+    }
+    return [ret[0], ret[1], ret[3], ret[4], ret[5], ret[6]];
+}
+
+/**
+ * Request without blocking. Return a ticket at once.
+ * The response can be fetched by `sys_poll` or `sys_wait_response`.
+ * Fail with SERVICE_ERROR if SERVICE_ASYNC_LIMIT responses are not taken.
+ */
+pub fn sys_request_async(args : Argument, port : usize, kind : usize) -> IPCTicket {
+    let mut ret : usize;
+    let args = args.to_registers();
     unsafe {
         core::arch::asm!(
             "ecall",
@@ -37,19 +105,60 @@ pub fn sys_request(args : Argument, port : usize, kind : usize) -> isize {
             in("a2") args[2],
             in("a4") kind,
             in("a6") port,
-            in("a7") SYS_REQUEST,
+            in("a7") SYS_REQUEST_ASYNC,
+            in("t1") args[3],
+            in("t2") args[4],
+            in("t3") args[5],
+            in("t4") args[6],
             lateout("a0") ret,
         );
     }
-    return ret;
+    return IPCTicket(ret);
+}
+
+/**
+ * Take one completed response, if any.
+ * Return the ticket and the response (length if copied into %buf).
+ */
+pub fn sys_poll(buf : &mut [u8]) -> Option<(IPCTicket, isize)> {
+    return complete_impl(false, buf);
+}
+
+/** Wait until some async request is completed. */
+pub fn sys_wait_response(buf : &mut [u8]) -> (IPCTicket, isize) {
+    return complete_impl(true, buf).unwrap();
+}
+
+fn complete_impl(block : bool, buf : &mut [u8]) -> Option<(IPCTicket, isize)> {
+    let mut ticket  : usize;
+    let mut result  : isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") block as usize,
+            in("a2") buf.as_mut_ptr() as usize,
+            in("a3") buf.len(),
+            in("a7") SYS_COMPLETE,
+            lateout("a0") ticket,
+            lateout("a1") result,
+        );
+    }
+    if ticket == 0 { return None; }
+    return Some((IPCTicket(ticket), result));
 }
 
 pub fn sys_receive(args : [usize; 3], port : usize) -> AcceptPacket {
+    return sys_receive_timeout(args, port, 0);
+}
+
+/** Receive with a timeout in milliseconds (0 for none). */
+pub fn sys_receive_timeout(args : [usize; 3], port : usize, timeout : usize) -> AcceptPacket {
     let mut kind    : usize;
     let mut result  : isize;
     let mut arg0    : usize;
     let mut arg1    : usize;
     let mut arg2    : usize;
+    let mut wide    = [0; 4];
     unsafe {
         core::arch::asm!(
             "ecall",
@@ -58,23 +167,64 @@ pub fn sys_receive(args : [usize; 3], port : usize) -> AcceptPacket {
             in("a2") args[2],
             in("a6") port,
             in("a7") SYS_RECEIVE,
+            in("t0") timeout,
             lateout("a0") arg0,
             lateout("a1") arg1,
             lateout("a2") arg2,
             lateout("a4") kind,
             lateout("a5") result,
+            lateout("t1") wide[0],
+            lateout("t2") wide[1],
+            lateout("t3") wide[2],
+            lateout("t4") wide[3],
         );
     }
     return AcceptPacket {
-        args: [arg0, arg1, arg2], kind, result
+        args: [arg0, arg1, arg2], wide, kind, result, port
     };
 }
 
-pub fn sys_respond(args : Argument, handle : IPCHandle) -> isize {
-    let args = match args {
-        Argument::Register(a0, a1) => [a0, a1, ARGS_REGISTER],
-        Argument::Buffered(a0, a1) => [a0 as usize, a1, ARGS_BUFFERED],
+/**
+ * Receive on any of %ports (the former preferred), with a timeout
+ * in milliseconds (0 for none). The port is in the packet.
+ */
+pub fn sys_receive_any(args : [usize; 3], ports : &[usize], timeout : usize) -> AcceptPacket {
+    let mut kind    : usize;
+    let mut result  : isize;
+    let mut arg0    : usize;
+    let mut arg1    : usize;
+    let mut arg2    : usize;
+    let mut wide    = [0; 4];
+    let mut port    : usize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") args[0],
+            in("a1") args[1],
+            in("a2") args[2],
+            in("a3") ports.as_ptr() as usize,
+            in("a5") ports.len(),
+            in("a7") SYS_RECEIVE_ANY,
+            in("t0") timeout,
+            lateout("a0") arg0,
+            lateout("a1") arg1,
+            lateout("a2") arg2,
+            lateout("a4") kind,
+            lateout("a5") result,
+            lateout("t1") wide[0],
+            lateout("t2") wide[1],
+            lateout("t3") wide[2],
+            lateout("t4") wide[3],
+            lateout("a6") port,
+        );
+    }
+    return AcceptPacket {
+        args: [arg0, arg1, arg2], wide, kind, result, port
     };
+}
+
+pub fn sys_respond(args : Argument, handle : IPCHandle) -> isize {
+    let args = args.to_registers();
 
     let mut ret : isize;
     unsafe {
@@ -85,14 +235,154 @@ pub fn sys_respond(args : Argument, handle : IPCHandle) -> isize {
             in("a2") args[2],
             in("a5") handle.0,
             in("a7") SYS_RESPOND,
+            in("t1") args[3],
+            in("t2") args[4],
+            in("t3") args[5],
+            in("t4") args[6],
             lateout("a0") ret,
         );
     }
     return ret;
 }
 
+/**
+ * Respond to %handle, and then receive the next request on %port,
+ * with the receive buffer %buffer (pointer + length, or zeros).
+ * Return the result of the response, and the packet received.
+ */
+pub fn sys_reply_and_receive(args : Argument, handle : IPCHandle, buffer : [usize; 2], port : usize, timeout : usize) -> (isize, AcceptPacket) {
+    let args = args.to_registers();
+
+    let mut kind    : usize;
+    let mut result  : isize;
+    let mut arg0    : usize;
+    let mut arg1    : usize;
+    let mut arg2    : usize;
+    let mut wide    = [0; 4];
+    let mut ret     : isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a0") args[0],
+            in("a1") args[1],
+            in("a2") args[2],
+            inlateout("a3") buffer[0] => ret,
+            inlateout("a4") buffer[1] => kind,
+            inlateout("a5") handle.0 => result,
+            in("a6") port,
+            in("a7") SYS_REPLY_RECEIVE,
+            in("t0") timeout,
+            inlateout("t1") args[3] => wide[0],
+            inlateout("t2") args[4] => wide[1],
+            inlateout("t3") args[5] => wide[2],
+            inlateout("t4") args[6] => wide[3],
+            lateout("a0") arg0,
+            lateout("a1") arg1,
+            lateout("a2") arg2,
+        );
+    }
+    return (ret, AcceptPacket {
+        args: [arg0, arg1, arg2], wide, kind, result, port
+    });
+}
+
+/** Claim a port by %name. Return None if the name is taken, or too many ports are owned. */
+pub fn sys_register(name : &str) -> Option<usize> {
+    let ret = syscall3(SYS_REGISTER, [name.as_ptr() as usize, name.len(), 0]);
+    return parse_port(ret);
+}
+
+/**
+ * Claim a port by %name, and get notified when a request is cancelled
+ * (i.e. the requester has died). The notification is an async request
+ * of kind IPC_CANCEL, holding the cancelled handle in the first register.
+ */
+pub fn sys_register_notify(name : &str) -> Option<usize> {
+    let ret = syscall3(SYS_REGISTER, [name.as_ptr() as usize, name.len(), SERVICE_NOTIFY]);
+    return parse_port(ret);
+}
+
+/** Resolve %name into a port. Return None if no such service. */
+pub fn sys_lookup(name : &str) -> Option<usize> {
+    let ret = syscall2(SYS_LOOKUP, [name.as_ptr() as usize, name.len()]);
+    return parse_port(ret);
+}
+
+/** Release a port claimed by this process. */
+pub fn sys_unregister(port : usize) -> bool {
+    return syscall1(SYS_UNREGISTER, [port]) == 0;
+}
+
+/** Keep only %rights (CAP_*) on %port, e.g. before exec. */
+pub fn sys_restrict(port : usize, rights : usize) {
+    syscall2(SYS_RESTRICT, [port, rights]);
+}
+
+/**
+ * Read the counters of the first port no less than %port.
+ * Return that port with them, or None if no more.
+ */
+pub fn sys_ipc_stat(port : usize) -> Option<(usize, IpcStat)> {
+    let mut stat = IpcStat::default();
+    let ret = syscall2(SYS_IPC_STAT, [port, &mut stat as *mut IpcStat as usize]);
+    return parse_port(ret).map(|port| (port, stat));
+}
+
+/**
+ * Switch the IPC trace by %flag (IPC_TRACE_*), and take the oldest
+ * events into %events. Return the number of events taken.
+ */
+pub fn sys_ipc_trace(events : &mut [IpcEvent], flag : usize) -> usize {
+    let ret = syscall3(SYS_IPC_TRACE, [events.as_mut_ptr() as usize, events.len(), flag]);
+    return parse_port(ret).unwrap_or(0);
+}
+
+/** Make a movable handle of %rights on %port. */
+pub fn sys_handle_port(port : usize, rights : usize) -> Option<usize> {
+    return parse_port(syscall2(SYS_HANDLE_PORT, [port, rights]));
+}
+
+/** Make a movable handle to respond to the request of %handle. */
+pub fn sys_handle_reply(handle : IPCHandle) -> Option<usize> {
+    return parse_port(syscall1(SYS_HANDLE_REPLY, [handle.0]));
+}
+
+/** Drop a local handle. */
+pub fn sys_handle_close(index : usize) -> bool {
+    return syscall1(SYS_HANDLE_CLOSE, [index]) == 0;
+}
+
+fn parse_port(ret : isize) -> Option<usize> {
+    if ret as usize == SERVICE_ERROR || ret as usize == SERVICE_DENIED { return None; }
+    return Some(ret as usize);
+}
+
+impl Argument {
+    /** Registers a0, a1, a2 (mode) and t1 to t4. */
+    fn to_registers(self) -> [usize; 7] {
+        match self {
+            Argument::Register(a0, a1) => [a0, a1, ARGS_REGISTER, 0, 0, 0, 0],
+            Argument::Buffered(a0, a1) => [a0 as usize, a1, ARGS_BUFFERED, 0, 0, 0, 0],
+            Argument::Upointer(a0, a1) => [a0 as usize, a1, ARGS_UPOINTER, 0, 0, 0, 0],
+            Argument::Mapping(a0, a1)  => [a0 as usize, a1, ARGS_MAPPING, 0, 0, 0, 0],
+            Argument::Capability(a0, a1) => [a0, a1, ARGS_CAPABILITY, 0, 0, 0, 0],
+            Argument::Handle(a0, a1)     => [a0, a1, ARGS_HANDLE, 0, 0, 0, 0],
+            Argument::Wide(x) => [x[0], x[1], ARGS_WIDE, x[2], x[3], x[4], x[5]],
+        }
+    }
+}
+
 impl AcceptPacket {
+    /** The port where the request comes from. */
+    pub fn get_port(&self) -> usize { return self.port; }
+
     pub fn parse(&self) -> IPCEnum {
+        if self.args[2] == SERVICE_ERROR || self.args[2] == SERVICE_DENIED {
+            return IPCEnum::IPCError;
+        }
+        if self.args[2] == SERVICE_TIMEOUT {
+            return IPCEnum::IPCTimeout;
+        }
         if self.result < 0 {
             return IPCEnum::IPCFail(-self.result as usize);
         }
@@ -109,12 +399,22 @@ impl AcceptPacket {
         match self.args[2] {
             ARGS_REGISTER => Argument::Register(self.args[0], self.args[1]),
             ARGS_BUFFERED => Argument::Buffered(self.args[0] as *mut u8, self.args[1]),
+            ARGS_MAPPING  => Argument::Mapping(self.args[0] as *mut u8, self.args[1]),
+            ARGS_CAPABILITY => Argument::Capability(self.args[0], self.args[1]),
+            ARGS_HANDLE     => Argument::Handle(self.args[0], self.args[1]),
+            ARGS_WIDE       => {
+                let [a0, a1, _] = self.args;
+                let [t1, t2, t3, t4] = self.wide;
+                Argument::Wide([a0, a1, t1, t2, t3, t4])
+            },
             _ => panic!("Unknown kind of argument."),
         }
     }
 }
 
 impl IPCHandle {
+    /** The handle of a delegated request, received in Argument::Handle. */
+    pub fn from_delegated(value : usize) -> Self { return IPCHandle(value); }
     /** Get the process id of the process who have requested. */
     pub unsafe fn get_pid(&self) -> PidType { return PidType::new(handle_to_pid(self.0)); }
     /** Respond by a handle */
@@ -0,0 +1,57 @@
+use sys::syscall::*;
+
+use super::call::*;
+
+/**
+ * Map %len bytes of anonymous private memory with %prot (PROT_*),
+ * at %hint if it is free (null for anywhere). Return the address.
+ */
+pub fn sys_mmap(hint : *mut u8, len : usize, prot : usize) -> Option<*mut u8> {
+    let ret = syscall3(SYS_MMAP, [hint as usize, len, prot]) as usize;
+    if ret == SERVICE_ERROR { return None; }
+    return Some(ret as *mut u8);
+}
+
+/** Unmap the pages of [%address, %address + %len). */
+pub fn sys_munmap(address : *mut u8, len : usize) -> bool {
+    return syscall2(SYS_MUNMAP, [address as usize, len]) == 0;
+}
+
+/** Change the permission of [%address, %address + %len) to %prot (PROT_*). */
+pub fn sys_mprotect(address : *mut u8, len : usize, prot : usize) -> bool {
+    return syscall3(SYS_MPROTECT, [address as usize, len, prot]) == 0;
+}
+
+/** Create a zero-filled shared memory object of %size bytes. Return its handle. */
+pub fn sys_shm_create(size : usize) -> Option<usize> {
+    let ret = syscall1(SYS_SHM_CREATE, [size]) as usize;
+    if ret == SERVICE_ERROR { return None; }
+    return Some(ret);
+}
+
+/** Map the shared memory of %handle with %prot (PROT_*). Return the address. */
+pub fn sys_shm_map(handle : usize, prot : usize) -> Option<*mut u8> {
+    let ret = syscall2(SYS_SHM_MAP, [handle, prot]) as usize;
+    if ret == SERVICE_ERROR { return None; }
+    return Some(ret as *mut u8);
+}
+
+/** Unmap the shared memory mapped at %address. */
+pub fn sys_shm_unmap(address : *mut u8) -> bool {
+    return syscall1(SYS_SHM_UNMAP, [address as usize]) == 0;
+}
+
+/** Drop the shared memory of %handle. Mappings are kept until unmapped. */
+pub fn sys_shm_destroy(handle : usize) -> bool {
+    return syscall1(SYS_SHM_DESTROY, [handle]) == 0;
+}
+
+/**
+ * Set how to choose the process killed when out of memory (OOM_KILL_*).
+ * Return the old one. Only for the process manager.
+ */
+pub fn sys_oom_policy(policy : usize) -> Option<usize> {
+    let ret = syscall1(SYS_OOM_POLICY, [policy]) as usize;
+    if ret == SERVICE_ERROR || ret == SERVICE_DENIED { return None; }
+    return Some(ret);
+}
@@ -4,6 +4,7 @@ mod call;
 mod proc;
 mod file;
 mod thread;
+mod mm;
 
 #[allow(unused)]
-pub use {ipc::*, proc::*, file::*, thread::*};
+pub use {ipc::*, proc::*, file::*, thread::*, mm::*};
@@ -69,6 +69,36 @@ pub unsafe fn sys_getpid() -> isize {
     syscall0(SYS_GETPID)
 }
 
+/** Move the break by %increment. Return the old one, or SERVICE_ERROR as a pointer. */
 pub unsafe fn sys_sbrk(increment : isize) -> *mut u8 {
     syscall1(SYS_SBRK, [increment as usize]) as _
 }
+
+/** Return the limit of %resource (RLIMIT_*), or None if invalid. */
+pub fn sys_getrlimit(resource : usize) -> Option<usize> {
+    let ret = syscall1(SYS_GETRLIMIT, [resource]) as usize;
+    if !is_valid_limit(ret) { return None; }
+    return Some(ret);
+}
+
+/**
+ * Set the limit of %resource (RLIMIT_*) to %limit, at most RLIMIT_INFINITY.
+ * Return the old one, or None if invalid. Only the process manager may raise a limit.
+ */
+pub fn sys_setrlimit(resource : usize, limit : usize) -> Option<usize> {
+    let ret = syscall2(SYS_SETRLIMIT, [resource, limit]) as usize;
+    if !is_valid_limit(ret) { return None; }
+    return Some(ret);
+}
+
+/**
+ * Read the usage of the first process with pid no less than %pid.
+ * Return that pid with it, or None if no more. Others than the
+ * process manager may only read their own.
+ */
+pub fn sys_proc_stat(pid : usize) -> Option<(usize, ProcStat)> {
+    let mut stat = ProcStat::default();
+    let ret = syscall2(SYS_PROC_STAT, [pid, &mut stat as *mut ProcStat as usize]) as usize;
+    if ret == SERVICE_ERROR || ret == SERVICE_DENIED { return None; }
+    return Some((ret, stat));
+}